@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 
 pub fn main() -> Result<()> {
     let start = std::time::Instant::now();
@@ -14,79 +14,369 @@ pub fn main() -> Result<()> {
     Ok(())
 }
 
-fn parse_drawing(drawing: &str) -> Vec<Vec<char>> {
-    drawing
-        .lines()
-        .rev()
-        // .skip(1)
-        .enumerate()
-        .fold(Vec::<Vec<char>>::new(), |mut acc, (li, line)| {
-            if li == 0 {
-                line.chars()
-                    .skip(1)
-                    .step_by(4)
-                    .for_each(|_| acc.push(vec![]));
-            } else {
-                line.chars()
-                    .skip(1)
-                    .step_by(4)
-                    .enumerate()
-                    .filter(|(_, c)| *c != ' ')
-                    .for_each(|(i, c)| acc[i].push(c));
+// Bottom crate first
+type Stack = Vec<char>;
+
+// Stacks are numbered from 0, unlike in the procedure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+    pub count: usize,
+    pub from: usize,
+    pub to: usize,
+}
+
+// Stacks are located by their labels on the last line, and every crate belongs to the label
+// nearest to it, so lines may be ragged and labels may have more than one digit
+fn parse_drawing(drawing: &str) -> Result<Vec<Stack>> {
+    let lines = drawing.lines().collect::<Vec<_>>();
+    let (labels, rows) = lines
+        .split_last()
+        .ok_or_else(|| anyhow!("the drawing is empty"))?;
+
+    // Twice the position of the middle of each label, which keeps it an integer
+    let mut centres = vec![];
+    let chars = labels.chars().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == ' ' {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < chars.len() && chars[i] != ' ' {
+            i += 1;
+        }
+        let label = chars[start..i].iter().collect::<String>();
+        if label.parse::<usize>().ok() != Some(centres.len() + 1) {
+            bail!(
+                "line {}: expected stack {} but found '{}'",
+                lines.len(),
+                centres.len() + 1,
+                label
+            );
+        }
+        centres.push(start + i - 1);
+    }
+
+    let mut stacks = vec![vec![]; centres.len()];
+
+    for (li, line) in rows.iter().enumerate().rev() {
+        let height = rows.len() - 1 - li;
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i..] {
+                [' ', ..] => i += 1,
+                ['[', c, ']', ..] => {
+                    let pos = 2 * (i + 1);
+                    let stack = (0..centres.len())
+                        .min_by_key(|&s| centres[s].abs_diff(pos))
+                        .filter(|&s| centres[s].abs_diff(pos) <= 2)
+                        .ok_or_else(|| {
+                            anyhow!("line {}: crate {} isn't above any stack", li + 1, c)
+                        })?;
+                    if stacks[stack].len() != height {
+                        bail!("line {}: crate {} is floating", li + 1, c);
+                    }
+                    stacks[stack].push(c);
+                    i += 3;
+                }
+                _ => bail!(
+                    "line {}: unexpected '{}' at column {}",
+                    li + 1,
+                    chars[i],
+                    i + 1
+                ),
             }
-            acc
-        })
+        }
+    }
+
+    Ok(stacks)
 }
 
-pub fn solve_a() -> Result<String> {
-    let (drawing, procedure) = include_str!("../input").split_once("\n\n").unwrap();
-
-    let mut x = parse_drawing(drawing);
-
-    procedure
-        .lines()
-        .map(|line| line.splitn(6, ' '))
-        .map(|g| {
-            let x = g.collect::<Vec<_>>();
-            (
-                x[1].parse::<u16>().unwrap(),
-                x[3].parse::<usize>().unwrap(),
-                x[5].parse::<usize>().unwrap(),
-            )
-        })
-        .for_each(|(n, src, dst)| {
-            for _ in 0..n {
-                let tmp = x[src - 1].pop().unwrap();
-                x[dst - 1].push(tmp);
+fn parse_move(line: &str) -> Result<Move> {
+    let stack = |label: &str| match label.parse::<usize>()? {
+        0 => bail!("there is no stack 0"),
+        x => Ok(x - 1),
+    };
+
+    match line.split(' ').collect::<Vec<_>>().as_slice() {
+        ["move", count, "from", from, "to", to] => Ok(Move {
+            count: count.parse()?,
+            from: stack(from)?,
+            to: stack(to)?,
+        }),
+        _ => bail!("can't parse move '{}'", line),
+    }
+}
+
+fn parse_input(input: &str) -> Result<(Vec<Stack>, Vec<Move>)> {
+    let (drawing, procedure) = input
+        .split_once("\n\n")
+        .ok_or_else(|| anyhow!("expected a drawing and a procedure"))?;
+
+    let stacks = parse_drawing(drawing)?;
+    let first_line = drawing.lines().count() + 2;
+
+    // Both cranes leave the same number of crates on each stack, so the moves can be checked
+    // without knowing which crane runs them
+    let mut heights = stacks.iter().map(|s| s.len()).collect::<Vec<_>>();
+    let mut moves = vec![];
+
+    for (i, line) in procedure.lines().enumerate() {
+        let check = || -> Result<Move> {
+            let m = parse_move(line)?;
+            for s in [m.from, m.to] {
+                if s >= heights.len() {
+                    bail!("there is no stack {}", s + 1);
+                }
             }
-        });
+            if heights[m.from] < m.count {
+                bail!(
+                    "can't move {} crates from stack {} which only has {}",
+                    m.count,
+                    m.from + 1,
+                    heights[m.from]
+                );
+            }
+            Ok(m)
+        };
+
+        let m = check().map_err(|e| anyhow!("line {}: {}", first_line + i, e))?;
+        heights[m.from] -= m.count;
+        heights[m.to] += m.count;
+        moves.push(m);
+    }
+
+    Ok((stacks, moves))
+}
+
+// Draws the stacks in the same format as the puzzle input. Columns are widened if the labels
+// need more room.
+pub fn render(stacks: &[Stack]) -> String {
+    let width = std::cmp::max(3, stacks.len().to_string().len() + 2);
+    let centre = |s: String| {
+        let left = (width - s.len()) / 2;
+        format!(
+            "{}{}{}",
+            " ".repeat(left),
+            s,
+            " ".repeat(width - s.len() - left)
+        )
+    };
+
+    let height = stacks.iter().map(|s| s.len()).max().unwrap_or(0);
+    let mut out = String::new();
+
+    for row in (0..height).rev() {
+        let cells = stacks
+            .iter()
+            .map(|s| match s.get(row) {
+                Some(c) => centre(format!("[{}]", c)),
+                None => " ".repeat(width),
+            })
+            .collect::<Vec<_>>();
+        out.push_str(&cells.join(" "));
+        out.push('\n');
+    }
+
+    let labels = (1..=stacks.len())
+        .map(|i| centre(i.to_string()))
+        .collect::<Vec<_>>();
+    out.push_str(&labels.join(" "));
+    out.push('\n');
+
+    out
+}
+
+pub fn tops(stacks: &[Stack]) -> String {
+    stacks.iter().filter_map(|s| s.last()).collect()
+}
+
+pub trait Crane {
+    fn apply(&self, stacks: &mut [Stack], m: &Move);
+}
 
-    let out = x.iter().map(|stack| stack.last().unwrap()).collect();
-    Ok(out)
+// Moves crates one at a time
+pub struct CrateMover9000;
+
+impl Crane for CrateMover9000 {
+    fn apply(&self, stacks: &mut [Stack], m: &Move) {
+        for _ in 0..m.count {
+            let tmp = stacks[m.from].pop().unwrap();
+            stacks[m.to].push(tmp);
+        }
+    }
+}
+
+// Moves all the crates at once, keeping their order
+pub struct CrateMover9001;
+
+impl Crane for CrateMover9001 {
+    fn apply(&self, stacks: &mut [Stack], m: &Move) {
+        let src_len = stacks[m.from].len();
+        let mgrp = stacks[m.from]
+            .drain(src_len - m.count..)
+            .collect::<Vec<_>>();
+        stacks[m.to].extend_from_slice(&mgrp);
+    }
+}
+
+// Runs the rearrangement procedure one move at a time
+pub struct Executor<C: Crane> {
+    crane: C,
+    stacks: Vec<Stack>,
+    moves: Vec<Move>,
+    next: usize,
+}
+
+impl<C: Crane> Executor<C> {
+    pub fn new(crane: C, input: &str) -> Result<Executor<C>> {
+        let (stacks, moves) = parse_input(input)?;
+        Ok(Executor {
+            crane,
+            stacks,
+            moves,
+            next: 0,
+        })
+    }
+
+    // Applies the next move and returns it, or None once the procedure is finished
+    pub fn step(&mut self) -> Option<Move> {
+        let m = *self.moves.get(self.next)?;
+        self.crane.apply(&mut self.stacks, &m);
+        self.next += 1;
+        Some(m)
+    }
+
+    pub fn run(&mut self) {
+        while self.step().is_some() {}
+    }
+
+    pub fn stacks(&self) -> &[Stack] {
+        &self.stacks
+    }
+
+    pub fn render(&self) -> String {
+        render(&self.stacks)
+    }
+
+    pub fn tops(&self) -> String {
+        tops(&self.stacks)
+    }
+}
+
+pub fn solve_a() -> Result<String> {
+    let mut ex = Executor::new(CrateMover9000, include_str!("../input"))?;
+    ex.run();
+    Ok(ex.tops())
 }
 
 pub fn solve_b() -> Result<String> {
-    let (drawing, procedure) = include_str!("../input").split_once("\n\n").unwrap();
-
-    let mut x = parse_drawing(drawing);
-
-    procedure
-        .lines()
-        .map(|line| line.splitn(6, ' '))
-        .map(|g| {
-            let x = g.collect::<Vec<_>>();
-            (
-                x[1].parse::<usize>().unwrap(),
-                x[3].parse::<usize>().unwrap(),
-                x[5].parse::<usize>().unwrap(),
+    let mut ex = Executor::new(CrateMover9001, include_str!("../input"))?;
+    ex.run();
+    Ok(ex.tops())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Drawings are padded to full width, which is easy to lose in a string literal
+    fn trimmed(drawing: String) -> String {
+        drawing
+            .lines()
+            .map(|l| format!("{}\n", l.trim_end()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let input = include_str!("../example");
+        let drawing = input.split_once("\n\n").unwrap().0;
+
+        let ex = Executor::new(CrateMover9000, input).unwrap();
+        assert_eq!(ex.render(), format!("{}\n", drawing));
+    }
+
+    #[test]
+    fn test_cranes() {
+        let input = include_str!("../example");
+
+        let mut ex = Executor::new(CrateMover9000, input).unwrap();
+        assert_eq!(
+            ex.step(),
+            Some(Move {
+                count: 1,
+                from: 1,
+                to: 0
+            })
+        );
+        assert_eq!(
+            trimmed(ex.render()),
+            "\
+[D]
+[N] [C]
+[Z] [M] [P]
+ 1   2   3
+"
+        );
+        ex.run();
+        assert_eq!(ex.step(), None);
+        assert_eq!(ex.tops(), "CMZ");
+
+        let mut ex = Executor::new(CrateMover9001, input).unwrap();
+        ex.step();
+        ex.step();
+        assert_eq!(
+            trimmed(ex.render()),
+            concat!(
+                "        [D]\n",
+                "        [N]\n",
+                "    [C] [Z]\n",
+                "    [M] [P]\n",
+                " 1   2   3\n",
             )
-        })
-        .for_each(|(n, src, dst)| {
-            let src_len = x[src - 1].len();
-            let mgrp = x[src - 1].drain(src_len - n..).collect::<Vec<_>>();
-            x[dst - 1].extend_from_slice(&mgrp);
-        });
-
-    let out = x.iter().map(|stack| stack.last().unwrap()).collect();
-    Ok(out)
+        );
+        ex.run();
+        assert_eq!(ex.tops(), "MCD");
+    }
+
+    #[test]
+    fn test_parse() {
+        let input = include_str!("../example");
+        let ragged = input
+            .lines()
+            .map(|l| format!("{}\n", l.trim_end()))
+            .collect::<String>();
+        assert_eq!(parse_input(&ragged).unwrap(), parse_input(input).unwrap());
+
+        let stacks = (0..11)
+            .map(|i| (0..i % 4).map(|j| (b'A' + (i + j) as u8) as char).collect())
+            .collect::<Vec<Stack>>();
+        let drawing = render(&stacks);
+        assert!(drawing.ends_with(" 10   11 \n"));
+        assert_eq!(parse_drawing(&drawing).unwrap(), stacks);
+        assert_eq!(parse_drawing(&trimmed(drawing)).unwrap(), stacks);
+
+        let drawing = "[A]\n    [B]\n 1   2\n\n";
+        let err = parse_input(drawing).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: crate A is floating");
+
+        let drawing = "[A] [B]\n 1   3\n\n";
+        let err = parse_input(drawing).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: expected stack 2 but found '3'");
+
+        let drawing = "[A] [B]\n 1   2\n\nmove 1 from 1 to 2\nmove 3 from 2 to 1\n";
+        let err = parse_input(drawing).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 5: can't move 3 crates from stack 2 which only has 2"
+        );
+
+        let drawing = "[A] [B]\n 1   2\n\nmove 1 from 1 to 3\n";
+        let err = parse_input(drawing).unwrap_err();
+        assert_eq!(err.to_string(), "line 4: there is no stack 3");
+    }
 }
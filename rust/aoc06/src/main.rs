@@ -1,8 +1,17 @@
-use std::collections::HashSet;
+use std::io::{ErrorKind, Read};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 
 pub fn main() -> Result<()> {
+    let args = std::env::args().collect::<Vec<_>>();
+    match &args[1..] {
+        [] => {}
+        [window] => return report(std::io::stdin().lock(), window),
+        [window, path] if path == "-" => return report(std::io::stdin().lock(), window),
+        [window, path] => return report(std::fs::File::open(path)?, window),
+        _ => bail!("usage: {} [<window> [<file>|-]]", args[0]),
+    }
+
     let start = std::time::Instant::now();
     let soln_a = solve_a()?;
     eprintln!("Part A elapsed {:?}", start.elapsed());
@@ -16,22 +25,94 @@ pub fn main() -> Result<()> {
     Ok(())
 }
 
-fn find_start(stream: &[u8], block_size: usize) -> Result<usize> {
-    let mut h = HashSet::with_capacity(block_size);
-    for (i, w) in stream.windows(block_size).enumerate() {
-        let processed = i + block_size;
-        h.clear();
-        for e in w.iter() {
-            if !h.insert(e) {
-                break;
-            }
+// Tracks the longest run of distinct bytes ending at the latest byte, using the position each
+// byte value was last seen at, so every byte is handled in constant time
+pub struct MarkerFinder {
+    window: usize,
+    // One past the position each byte value was last seen at, or 0 if it hasn't been seen
+    last: [usize; 256],
+    // Start of the longest run of distinct bytes ending at the latest byte
+    run_start: usize,
+    processed: usize,
+}
+
+impl MarkerFinder {
+    pub fn new(window: usize) -> Result<MarkerFinder> {
+        if window == 0 {
+            bail!("markers need at least one byte");
         }
-        if h.len() == block_size {
-            return Ok(processed);
+
+        Ok(MarkerFinder {
+            window,
+            last: [0; 256],
+            run_start: 0,
+            processed: 0,
+        })
+    }
+
+    // Feeds the next byte, returning true if it completes a marker
+    pub fn push(&mut self, b: u8) -> bool {
+        self.run_start = std::cmp::max(self.run_start, self.last[b as usize]);
+        self.processed += 1;
+        self.last[b as usize] = self.processed;
+
+        self.processed - self.run_start >= self.window
+    }
+
+    pub fn processed(&self) -> usize {
+        self.processed
+    }
+}
+
+// Every position at which the preceding `window` bytes are all different
+pub fn find_all(stream: &[u8], window: usize) -> Result<Vec<usize>> {
+    let mut finder = MarkerFinder::new(window)?;
+
+    Ok(stream
+        .iter()
+        .enumerate()
+        .filter(|&(_, &b)| finder.push(b))
+        .map(|(i, _)| i + 1)
+        .collect())
+}
+
+pub fn find_start(stream: &[u8], window: usize) -> Result<usize> {
+    let mut finder = MarkerFinder::new(window)?;
+
+    stream
+        .iter()
+        .position(|&b| finder.push(b))
+        .map(|i| i + 1)
+        .ok_or_else(|| anyhow!("Could not find start"))
+}
+
+// Reads `reader` in chunks and stops as soon as the marker is found, so the stream never has to
+// fit in memory and may be endless
+pub fn find_start_in(mut reader: impl Read, window: usize) -> Result<usize> {
+    let mut finder = MarkerFinder::new(window)?;
+    let mut buf = vec![0; 1 << 16];
+
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => bail!("Could not find start"),
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        if buf[..n].iter().any(|&b| finder.push(b)) {
+            return Ok(finder.processed());
         }
     }
+}
+
+fn report(reader: impl Read, window: &str) -> Result<()> {
+    let start = std::time::Instant::now();
+    let marker = find_start_in(reader, window.parse()?)?;
+    eprintln!("Elapsed {:?}", start.elapsed());
+    println!("marker after {} bytes", marker);
 
-    Err(anyhow!("Could not find start"))
+    Ok(())
 }
 
 pub fn solve_a() -> Result<usize> {
@@ -43,3 +124,88 @@ pub fn solve_b() -> Result<usize> {
     let stream = include_bytes!("../input");
     find_start(stream, 14)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lcg::Lcg;
+
+    // The original approach, checking every window from scratch
+    fn find_all_naive(stream: &[u8], window: usize) -> Vec<usize> {
+        stream
+            .windows(window)
+            .enumerate()
+            .filter(|(_, w)| (1..w.len()).all(|i| !w[..i].contains(&w[i])))
+            .map(|(i, _)| i + window)
+            .collect()
+    }
+
+    #[test]
+    fn test_examples() {
+        let example1 = include_bytes!("../example1");
+        let example2 = include_bytes!("../example2");
+
+        assert_eq!(find_start(example1, 4).unwrap(), 11);
+        assert_eq!(find_start(example1, 14).unwrap(), 26);
+        assert_eq!(find_start(example2, 4).unwrap(), 10);
+        assert_eq!(find_start(example2, 14).unwrap(), 29);
+
+        assert!(find_start(b"aaaa", 2).is_err());
+        assert!(find_start(b"abcd", 0).is_err());
+    }
+
+    #[test]
+    fn test_find_all() {
+        assert_eq!(find_all(b"abcabba", 3).unwrap(), vec![3, 4, 5]);
+        assert_eq!(find_all(b"aab", 1).unwrap(), vec![1, 2, 3]);
+
+        // Pseudo-random streams over small alphabets, including bytes above 127
+        let mut rng = Lcg::new(1);
+        for alphabet in [2, 3, 5, 16, 256] {
+            let stream = (0..2000)
+                .map(|_| rng.below(alphabet) as u8)
+                .collect::<Vec<_>>();
+
+            for window in 1..=6 {
+                assert_eq!(
+                    find_all(&stream, window).unwrap(),
+                    find_all_naive(&stream, window)
+                );
+            }
+        }
+    }
+
+    // A stream that never ends, handed out a few bytes at a time: `prefix` copies of 'a', then
+    // "abcd", then 'z' forever
+    struct Endless {
+        prefix: usize,
+        pos: usize,
+    }
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = std::cmp::min(buf.len(), 1 + self.pos % 7);
+            for b in buf[..n].iter_mut() {
+                *b = match self.pos.checked_sub(self.prefix) {
+                    None => b'a',
+                    Some(i) if i < 4 => b"abcd"[i],
+                    Some(_) => b'z',
+                };
+                self.pos += 1;
+            }
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_reader() {
+        for prefix in [0, 1, 5, 1000, 100_000] {
+            let stream = Endless { prefix, pos: 0 };
+            assert_eq!(find_start_in(stream, 4).unwrap(), prefix + 4);
+        }
+
+        let example2 = include_bytes!("../example2");
+        assert_eq!(find_start_in(&example2[..], 14).unwrap(), 29);
+        assert!(find_start_in(&b"abcabc"[..], 4).is_err());
+    }
+}
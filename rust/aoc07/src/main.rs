@@ -1,6 +1,8 @@
-use anyhow::Result;
+use std::collections::hash_map::Entry as MapEntry;
 use std::collections::HashMap;
 
+use anyhow::{anyhow, bail, Result};
+
 pub fn main() -> Result<()> {
     let start = std::time::Instant::now();
     let soln_a = solve_a()?;
@@ -19,71 +21,662 @@ pub fn main() -> Result<()> {
 enum ProgLine<'a> {
     Cd(&'a str),
     Dir(&'a str),
-    File(usize),
+    File(&'a str, usize),
     Ls,
 }
 
-fn parse_line(line: &str) -> ProgLine {
-    match line {
-        x if x.starts_with("$ cd") => ProgLine::Cd(x.splitn(3, ' ').nth(2).unwrap()),
-        x if x.starts_with("$ ls") => ProgLine::Ls,
-        x if x.starts_with("dir") => ProgLine::Dir(x.split_once(' ').unwrap().1),
-        x => {
-            let (sz, _) = x.split_once(' ').unwrap();
-            ProgLine::File(sz.parse::<usize>().unwrap())
+fn parse_line(line: &str) -> Result<ProgLine<'_>> {
+    Ok(match line.split(' ').collect::<Vec<_>>().as_slice() {
+        ["$", "cd", x] => ProgLine::Cd(x),
+        ["$", "ls"] => ProgLine::Ls,
+        ["dir", x] => ProgLine::Dir(x),
+        [sz, x] => ProgLine::File(x, sz.parse()?),
+        _ => bail!("can't parse '{}'", line),
+    })
+}
+
+type DirId = usize;
+
+#[derive(Debug, Clone, Copy)]
+enum Entry {
+    Dir(DirId),
+    File(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    DuplicateListing {
+        dir: String,
+        first: usize,
+    },
+    UnknownDirectory(String),
+    CdAboveRoot,
+    ConflictingSize {
+        file: String,
+        first: usize,
+        second: usize,
+    },
+    NotADirectory(String),
+    NotAFile(String),
+}
+
+impl Problem {
+    fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            Problem::CdAboveRoot
+                | Problem::ConflictingSize { .. }
+                | Problem::NotADirectory(_)
+                | Problem::NotAFile(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub line: usize,
+    pub problem: Problem,
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.problem {
+            Problem::DuplicateListing { dir, first } => {
+                write!(f, "{} was already listed on line {}", dir, first)
+            }
+            Problem::UnknownDirectory(dir) => write!(f, "cd into {} before it was listed", dir),
+            Problem::CdAboveRoot => write!(f, "cd .. from /"),
+            Problem::ConflictingSize {
+                file,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} has size {} but was listed as {}",
+                file, second, first
+            ),
+            Problem::NotADirectory(path) => write!(f, "{} is a file", path),
+            Problem::NotAFile(path) => write!(f, "{} is a directory", path),
         }
     }
 }
 
-fn get_directory_sizes(input: &str) -> HashMap<String, usize> {
-    input
-        .lines()
-        .fold(
-            (Vec::<&str>::new(), HashMap::<String, usize>::new()),
-            |(mut cwd, mut dirsize), line| {
-                match parse_line(line) {
-                    ProgLine::Cd(x) => {
-                        if x == ".." {
-                            cwd.pop();
-                        } else {
-                            cwd.push(x);
-                        }
+// Every inconsistency in a transcript, in the order they occur
+pub fn validate(input: &str) -> Result<Vec<Issue>> {
+    Ok(FileSystem::replay(input)?.1)
+}
+
+// Entries are kept in the order they were first listed
+#[derive(Debug)]
+struct Dir {
+    name: String,
+    parent: Option<DirId>,
+    entries: Vec<(String, Entry)>,
+}
+
+// Directories are stored in creation order, so every directory comes after its parent and the
+// root is always 0
+#[derive(Debug)]
+pub struct FileSystem {
+    dirs: Vec<Dir>,
+}
+
+const ROOT: DirId = 0;
+
+impl FileSystem {
+    fn new() -> FileSystem {
+        FileSystem {
+            dirs: vec![Dir {
+                name: "/".to_string(),
+                parent: None,
+                entries: vec![],
+            }],
+        }
+    }
+
+    fn entry(&self, dir: DirId, name: &str) -> Option<Entry> {
+        self.dirs[dir]
+            .entries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|&(_, e)| e)
+    }
+
+    fn subdir(&mut self, dir: DirId, name: &str) -> Result<DirId, Problem> {
+        match self.entry(dir, name) {
+            Some(Entry::Dir(id)) => Ok(id),
+            Some(Entry::File(_)) => Err(Problem::NotADirectory(self.join(dir, name))),
+            None => {
+                let id = self.dirs.len();
+                self.dirs.push(Dir {
+                    name: name.to_string(),
+                    parent: Some(dir),
+                    entries: vec![],
+                });
+                self.dirs[dir]
+                    .entries
+                    .push((name.to_string(), Entry::Dir(id)));
+                Ok(id)
+            }
+        }
+    }
+
+    fn add_file(&mut self, dir: DirId, name: &str, size: usize) -> Result<(), Problem> {
+        match self.entry(dir, name) {
+            Some(Entry::Dir(_)) => Err(Problem::NotAFile(self.join(dir, name))),
+            Some(Entry::File(first)) if first != size => Err(Problem::ConflictingSize {
+                file: self.join(dir, name),
+                first,
+                second: size,
+            }),
+            Some(Entry::File(_)) => Ok(()),
+            None => {
+                self.dirs[dir]
+                    .entries
+                    .push((name.to_string(), Entry::File(size)));
+                Ok(())
+            }
+        }
+    }
+
+    // Builds the tree while collecting every inconsistency in the transcript. Where possible the
+    // replay carries on as if the transcript were right: an unknown directory is created, a
+    // repeated listing is merged and a conflicting file keeps its first size.
+    fn replay(input: &str) -> Result<(FileSystem, Vec<Issue>)> {
+        let mut fs = FileSystem::new();
+        let mut cwd = ROOT;
+        let mut listed: HashMap<DirId, usize> = HashMap::new();
+        let mut issues = vec![];
+
+        for (i, line) in input.lines().enumerate() {
+            let line_no = i + 1;
+            let res = match parse_line(line).map_err(|e| anyhow!("line {}: {}", line_no, e))? {
+                ProgLine::Cd("/") => {
+                    cwd = ROOT;
+                    Ok(())
+                }
+                ProgLine::Cd("..") => match fs.dirs[cwd].parent {
+                    Some(parent) => {
+                        cwd = parent;
+                        Ok(())
+                    }
+                    None => Err(Problem::CdAboveRoot),
+                },
+                ProgLine::Cd(x) => {
+                    if fs.entry(cwd, x).is_none() {
+                        issues.push(Issue {
+                            line: line_no,
+                            problem: Problem::UnknownDirectory(fs.join(cwd, x)),
+                        });
                     }
-                    ProgLine::Dir(_) => (),
-                    ProgLine::Ls => (),
-                    ProgLine::File(x) => cwd.iter().enumerate().for_each(|(i, _)| {
-                        let dirpath = cwd[..i + 1].join("/");
-                        *dirsize.entry(dirpath).or_insert(0) += x;
+                    fs.subdir(cwd, x).map(|id| cwd = id)
+                }
+                ProgLine::Ls => match listed.entry(cwd) {
+                    MapEntry::Occupied(e) => Err(Problem::DuplicateListing {
+                        dir: fs.path(cwd),
+                        first: *e.get(),
                     }),
+                    MapEntry::Vacant(e) => {
+                        e.insert(line_no);
+                        Ok(())
+                    }
+                },
+                ProgLine::Dir(x) => fs.subdir(cwd, x).map(|_| ()),
+                ProgLine::File(x, size) => fs.add_file(cwd, x, size),
+            };
+
+            if let Err(problem) = res {
+                issues.push(Issue {
+                    line: line_no,
+                    problem,
+                });
+            }
+        }
+
+        Ok((fs, issues))
+    }
+
+    // Fails on anything that leaves the shape of the tree in doubt, but tolerates repeated
+    // listings and directories that are entered before being listed
+    pub fn from_transcript(input: &str) -> Result<FileSystem> {
+        let (fs, issues) = FileSystem::replay(input)?;
+
+        if let Some(issue) = issues.iter().find(|i| i.problem.is_fatal()) {
+            bail!("{}", issue);
+        }
+
+        Ok(fs)
+    }
+
+    fn path(&self, dir: DirId) -> String {
+        match self.dirs[dir].parent {
+            None => "/".to_string(),
+            Some(parent) => self.join(parent, &self.dirs[dir].name),
+        }
+    }
+
+    fn join(&self, dir: DirId, name: &str) -> String {
+        match dir {
+            ROOT => format!("/{}", name),
+            _ => format!("{}/{}", self.path(dir), name),
+        }
+    }
+
+    // Total size of every directory, indexed by DirId
+    fn sizes(&self) -> Vec<usize> {
+        let mut sizes = self
+            .dirs
+            .iter()
+            .map(|d| {
+                d.entries
+                    .iter()
+                    .map(|(_, e)| match e {
+                        Entry::File(size) => *size,
+                        Entry::Dir(_) => 0,
+                    })
+                    .sum()
+            })
+            .collect::<Vec<usize>>();
+
+        for id in (1..self.dirs.len()).rev() {
+            let parent = self.dirs[id].parent.unwrap();
+            sizes[parent] += sizes[id];
+        }
+
+        sizes
+    }
+
+    // Every directory's path and total size, like `du`
+    pub fn du(&self) -> Vec<(String, usize)> {
+        self.sizes()
+            .into_iter()
+            .enumerate()
+            .map(|(id, size)| (self.path(id), size))
+            .collect()
+    }
+
+    pub fn used_space(&self) -> usize {
+        self.sizes()[ROOT]
+    }
+
+    // Directories whose total size satisfies `pred`, like `find -type d -size`
+    pub fn find_dirs(&self, pred: impl Fn(usize) -> bool) -> Vec<(String, usize)> {
+        self.du().into_iter().filter(|&(_, s)| pred(s)).collect()
+    }
+
+    // Files whose size satisfies `pred`, like `find -type f -size`
+    pub fn find_files(&self, pred: impl Fn(usize) -> bool) -> Vec<(String, usize)> {
+        (0..self.dirs.len())
+            .flat_map(|id| {
+                self.dirs[id]
+                    .entries
+                    .iter()
+                    .filter_map(move |(name, e)| match e {
+                        Entry::File(size) => Some((self.join(id, name), *size)),
+                        Entry::Dir(_) => None,
+                    })
+            })
+            .filter(|&(_, s)| pred(s))
+            .collect()
+    }
+
+    // Renders the tree in the same format as the puzzle description
+    pub fn tree(&self) -> String {
+        let mut out = String::new();
+        self.draw(ROOT, "/", 0, &mut out);
+        out
+    }
+
+    fn draw(&self, dir: DirId, name: &str, depth: usize, out: &mut String) {
+        out.push_str(&format!("{}- {} (dir)\n", "  ".repeat(depth), name));
+        for (name, e) in &self.dirs[dir].entries {
+            match e {
+                Entry::Dir(id) => self.draw(*id, name, depth + 1, out),
+                Entry::File(size) => out.push_str(&format!(
+                    "{}- {} (file, size={})\n",
+                    "  ".repeat(depth + 1),
+                    name,
+                    size
+                )),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disk {
+    pub capacity: usize,
+    pub required: usize,
+}
+
+impl Default for Disk {
+    fn default() -> Self {
+        Disk {
+            capacity: 70000000,
+            required: 30000000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deletion {
+    pub dirs: Vec<(String, usize)>,
+    pub freed: usize,
+    // False if the search ran out of steps before it could rule out a better set
+    pub optimal: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    pub to_free: usize,
+    // The smallest directory that frees enough space on its own
+    pub single: Option<(String, usize)>,
+    // The set of directories that frees enough space while deleting as little as possible
+    pub set: Option<Deletion>,
+}
+
+const SEARCH_STEPS: usize = 10_000_000;
+
+// Directories in preorder, so that each directory's subtree is the range `i..end[i]`
+struct Preorder {
+    order: Vec<DirId>,
+    end: Vec<usize>,
+}
+
+impl FileSystem {
+    fn preorder(&self) -> Preorder {
+        let mut p = Preorder {
+            order: vec![],
+            end: vec![0; self.dirs.len()],
+        };
+        self.visit(ROOT, &mut p);
+        p
+    }
+
+    fn visit(&self, dir: DirId, p: &mut Preorder) {
+        let i = p.order.len();
+        p.order.push(dir);
+        for (_, e) in &self.dirs[dir].entries {
+            if let Entry::Dir(id) = e {
+                self.visit(*id, p);
+            }
+        }
+        p.end[i] = p.order.len();
+    }
+
+    pub fn plan(&self, disk: Disk, find_set: bool) -> Plan {
+        let sizes = self.sizes();
+        let to_free = (sizes[ROOT] + disk.required).saturating_sub(disk.capacity);
+
+        if to_free == 0 {
+            let set = Deletion {
+                dirs: vec![],
+                freed: 0,
+                optimal: true,
+            };
+            return Plan {
+                to_free,
+                single: None,
+                set: find_set.then_some(set),
+            };
+        }
+
+        let single = (0..self.dirs.len())
+            .filter(|&id| sizes[id] >= to_free)
+            .min_by_key(|&id| sizes[id])
+            .map(|id| (self.path(id), sizes[id]));
+
+        let set = match find_set {
+            true => self.best_set(&sizes, to_free),
+            false => None,
+        };
+
+        Plan {
+            to_free,
+            single,
+            set,
+        }
+    }
+
+    // Branch and bound over the directories in preorder, where deleting a directory skips its
+    // whole subtree. Stops early once a set frees exactly `to_free`.
+    fn best_set(&self, sizes: &[usize], to_free: usize) -> Option<Deletion> {
+        struct Search<'a> {
+            p: Preorder,
+            sizes: &'a [usize],
+            // Most that can be freed by deleting directories from position i onwards
+            avail: Vec<usize>,
+            to_free: usize,
+            chosen: Vec<usize>,
+            best: Option<(usize, Vec<usize>)>,
+            steps: usize,
+        }
+
+        impl Search<'_> {
+            fn best_freed(&self) -> usize {
+                self.best.as_ref().map_or(usize::MAX, |b| b.0)
+            }
+
+            fn go(&mut self, i: usize, freed: usize) {
+                self.steps += 1;
+                if freed >= self.to_free {
+                    if freed < self.best_freed() {
+                        self.best = Some((freed, self.chosen.clone()));
+                    }
+                    return;
                 }
-                (cwd, dirsize)
-            },
-        )
-        .1
+                if self.steps > SEARCH_STEPS
+                    || self.best_freed() == self.to_free
+                    || freed + self.avail[i] < self.to_free
+                {
+                    return;
+                }
+
+                let size = self.sizes[self.p.order[i]];
+                if freed + size < self.best_freed() {
+                    self.chosen.push(i);
+                    self.go(self.p.end[i], freed + size);
+                    self.chosen.pop();
+                }
+                self.go(i + 1, freed);
+            }
+        }
+
+        let p = self.preorder();
+        let n = p.order.len();
+        let mut avail = vec![0; n + 1];
+        for i in (0..n).rev() {
+            avail[i] = std::cmp::max(avail[i + 1], sizes[p.order[i]] + avail[p.end[i]]);
+        }
+
+        let mut search = Search {
+            p,
+            sizes,
+            avail,
+            to_free,
+            chosen: vec![],
+            best: None,
+            steps: 0,
+        };
+        search.go(0, 0);
+
+        let optimal = search.steps <= SEARCH_STEPS || search.best_freed() == to_free;
+        search.best.map(|(freed, chosen)| Deletion {
+            dirs: chosen
+                .into_iter()
+                .map(|i| {
+                    let id = search.p.order[i];
+                    (self.path(id), sizes[id])
+                })
+                .collect(),
+            freed,
+            optimal,
+        })
+    }
 }
 
 pub fn solve_a() -> Result<usize> {
-    let x = get_directory_sizes(include_str!("../input"))
-        .values()
-        .filter(|x| **x <= 100000)
-        .sum();
+    let fs = FileSystem::from_transcript(include_str!("../input"))?;
+    let x = fs.find_dirs(|s| s <= 100000).iter().map(|(_, s)| s).sum();
 
     Ok(x)
 }
 
 pub fn solve_b() -> Result<usize> {
-    let dirsize = get_directory_sizes(include_str!("../input"));
+    let fs = FileSystem::from_transcript(include_str!("../input"))?;
+
+    let (_, x) = fs
+        .plan(Disk::default(), false)
+        .single
+        .ok_or_else(|| anyhow!("no directory is big enough"))?;
+
+    Ok(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree() {
+        let fs = FileSystem::from_transcript(include_str!("../example1")).unwrap();
 
-    let used_space = dirsize.get("/").unwrap();
-    let free_space = 70000000 - used_space;
-    let min_delete_size = 30000000 - free_space;
+        assert_eq!(
+            fs.tree(),
+            "\
+- / (dir)
+  - a (dir)
+    - e (dir)
+      - i (file, size=584)
+    - f (file, size=29116)
+    - g (file, size=2557)
+    - h.lst (file, size=62596)
+  - b.txt (file, size=14848514)
+  - c.dat (file, size=8504156)
+  - d (dir)
+    - j (file, size=4060174)
+    - d.log (file, size=8033020)
+    - d.ext (file, size=5626152)
+    - k (file, size=7214296)
+"
+        );
+
+        assert_eq!(
+            fs.du(),
+            vec![
+                ("/".to_string(), 48381165),
+                ("/a".to_string(), 94853),
+                ("/d".to_string(), 24933642),
+                ("/a/e".to_string(), 584),
+            ]
+        );
+        assert_eq!(
+            fs.find_files(|s| s > 8000000),
+            vec![
+                ("/b.txt".to_string(), 14848514),
+                ("/c.dat".to_string(), 8504156),
+                ("/d/d.log".to_string(), 8033020),
+            ]
+        );
+    }
 
-    let x = dirsize
-        .values()
-        .filter(|&s| *s >= min_delete_size)
-        .copied()
-        .min()
+    #[test]
+    fn test_cd_root() {
+        let fs = FileSystem::from_transcript(
+            "$ cd /\n$ ls\ndir a\n$ cd a\n$ ls\n10 x\n$ cd /\n$ ls\n20 y\n",
+        )
         .unwrap();
+        assert_eq!(fs.du(), vec![("/".to_string(), 30), ("/a".to_string(), 10)]);
 
-    Ok(x)
+        assert!(FileSystem::from_transcript("$ cd /\n$ cd ..\n").is_err());
+    }
+
+    #[test]
+    fn test_plan() {
+        let fs = FileSystem::from_transcript(include_str!("../example1")).unwrap();
+
+        let plan = fs.plan(Disk::default(), true);
+        assert_eq!(plan.to_free, 8381165);
+        assert_eq!(plan.single, Some(("/d".to_string(), 24933642)));
+        assert_eq!(
+            plan.set,
+            Some(Deletion {
+                dirs: vec![("/d".to_string(), 24933642)],
+                freed: 24933642,
+                optimal: true
+            })
+        );
+
+        // Neither /a nor /d is enough on its own, but together /a/e and /d free exactly enough
+        let disk = Disk {
+            capacity: 53446939,
+            required: 30000000,
+        };
+        let plan = fs.plan(disk, true);
+        assert_eq!(plan.to_free, 24934226);
+        assert_eq!(plan.single, Some(("/".to_string(), 48381165)));
+        assert_eq!(
+            plan.set,
+            Some(Deletion {
+                dirs: vec![("/a/e".to_string(), 584), ("/d".to_string(), 24933642)],
+                freed: 24934226,
+                optimal: true
+            })
+        );
+
+        let disk = Disk {
+            capacity: 100000000,
+            required: 1000,
+        };
+        let plan = fs.plan(disk, true);
+        assert_eq!(plan.to_free, 0);
+        assert_eq!(plan.single, None);
+        assert_eq!(plan.set.unwrap().dirs, vec![]);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate(include_str!("../example1")).unwrap(), vec![]);
+
+        let transcript = "\
+$ cd /
+$ ls
+dir a
+10 x
+$ cd b
+$ ls
+5 y
+$ cd ..
+$ ls
+20 x
+dir x
+$ cd ..
+$ cd a
+$ cd ..
+$ cd x
+";
+        let issues = validate(transcript)
+            .unwrap()
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            issues,
+            vec![
+                "line 5: cd into /b before it was listed",
+                "line 9: / was already listed on line 2",
+                "line 10: /x has size 20 but was listed as 10",
+                "line 11: /x is a file",
+                "line 12: cd .. from /",
+                "line 15: /x is a file",
+            ]
+        );
+
+        let err = FileSystem::from_transcript(transcript).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 10: /x has size 20 but was listed as 10"
+        );
+    }
 }
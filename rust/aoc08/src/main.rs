@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 pub fn main() -> Result<()> {
     let start = std::time::Instant::now();
@@ -14,149 +14,233 @@ pub fn main() -> Result<()> {
     Ok(())
 }
 
-fn parse_input(input: &[u8]) -> Vec<Vec<u8>> {
-    input
-        .split(|b| *b == b'\n')
-        .map(|line| line.iter().map(|b| b - b'0').collect())
-        .collect()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    North,
+    South,
+    East,
+    West,
 }
 
-pub fn solve_a() -> Result<usize> {
-    let map = parse_input(include_bytes!("../input"));
-    let nrows = map.len();
-    let ncols = map[0].len();
-    let mut is_visible = vec![vec![false; ncols]; nrows];
-    // let mut cummax = vec![vec![0; ncols]; nrows];
-
-    // West-to-East
-    for i in 0..nrows {
-        let mut max_height = 0u8;
-        for j in 0..ncols {
-            let v = map[i][j];
-            if j == 0 {
-                is_visible[i][j] = true;
-                max_height = v;
-                continue;
-            }
+pub const DIRECTIONS: [Direction; 4] = [
+    Direction::North,
+    Direction::South,
+    Direction::East,
+    Direction::West,
+];
+
+pub struct Forest {
+    heights: Vec<Vec<u8>>,
+    nrows: usize,
+    ncols: usize,
+}
 
-            if v > max_height {
-                is_visible[i][j] = true;
-                max_height = v;
-            } else {
-                is_visible[i][j] = false;
-            }
-        }
-    }
+// What each tree sees looking in one direction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sight {
+    // Whether each tree can be seen from outside the forest
+    pub visible: Vec<Vec<bool>>,
+    // How many trees each tree can see
+    pub distance: Vec<Vec<usize>>,
+}
 
-    // East-to-West
-    for i in 0..nrows {
-        let mut max_height = 0u8;
-        for j in (0..ncols).rev() {
-            let v = map[i][j];
-            if j == ncols - 1 {
-                is_visible[i][j] = true;
-                max_height = v;
-                continue;
-            }
+// Every direction combined
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Survey {
+    // Whether each tree can be seen from any edge of the forest
+    pub visible: Vec<Vec<bool>>,
+    // The product of each tree's viewing distances
+    pub scenic: Vec<Vec<usize>>,
+}
 
-            if v > max_height {
-                is_visible[i][j] |= true;
-                max_height = v;
-            } else {
-                is_visible[i][j] |= false;
-            }
-        }
+fn parse_input(input: &[u8]) -> Result<Forest> {
+    let heights = input
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            line.iter()
+                .map(|b| match b {
+                    b'0'..=b'9' => Ok(b - b'0'),
+                    _ => bail!("line {}: unexpected '{}'", i + 1, *b as char),
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let nrows = heights.len();
+    let ncols = heights.first().map_or(0, |r| r.len());
+    if let Some(i) = heights.iter().position(|r| r.len() != ncols) {
+        bail!("line {}: expected {} trees", i + 1, ncols);
     }
 
-    // North-to-South
-    for j in 0..ncols {
-        let mut max_height = 0u8;
-        for i in 0..nrows {
-            let v = map[i][j];
-            if i == 0 {
-                is_visible[i][j] = true;
-                max_height = v;
-                continue;
-            }
+    Ok(Forest {
+        heights,
+        nrows,
+        ncols,
+    })
+}
 
-            if v > max_height {
-                is_visible[i][j] |= true;
-                max_height = v;
-            } else {
-                is_visible[i][j] |= false;
-            }
+impl Forest {
+    // Each row or column as (row, col) pairs, starting at the edge the trees look towards
+    fn lines(&self, dir: Direction) -> Vec<Vec<(usize, usize)>> {
+        let (nrows, ncols) = (self.nrows, self.ncols);
+        match dir {
+            Direction::West => (0..nrows)
+                .map(|i| (0..ncols).map(|j| (i, j)).collect())
+                .collect(),
+            Direction::East => (0..nrows)
+                .map(|i| (0..ncols).rev().map(|j| (i, j)).collect())
+                .collect(),
+            Direction::North => (0..ncols)
+                .map(|j| (0..nrows).map(|i| (i, j)).collect())
+                .collect(),
+            Direction::South => (0..ncols)
+                .map(|j| (0..nrows).rev().map(|i| (i, j)).collect())
+                .collect(),
         }
     }
 
-    // South-to-North
-    for j in 0..ncols {
-        let mut max_height = 0u8;
-        for i in (0..nrows).rev() {
-            let v = map[i][j];
-            if i == nrows - 1 {
-                is_visible[i][j] = true;
-                max_height = v;
-                continue;
+    // Walks each line inwards from the edge in `dir`, keeping a stack of the trees that could
+    // still block the view, tallest at the bottom. Every tree is pushed and popped once, so this
+    // is linear in the size of the forest.
+    pub fn look(&self, dir: Direction) -> Sight {
+        let mut sight = Sight {
+            visible: vec![vec![false; self.ncols]; self.nrows],
+            distance: vec![vec![0; self.ncols]; self.nrows],
+        };
+
+        for line in self.lines(dir) {
+            let mut stack: Vec<usize> = vec![];
+
+            for (k, &(i, j)) in line.iter().enumerate() {
+                let h = self.heights[i][j];
+                while let Some(&top) = stack.last() {
+                    let (ti, tj) = line[top];
+                    if self.heights[ti][tj] >= h {
+                        break;
+                    }
+                    stack.pop();
+                }
+
+                match stack.last() {
+                    Some(&top) => sight.distance[i][j] = k - top,
+                    None => {
+                        sight.visible[i][j] = true;
+                        sight.distance[i][j] = k;
+                    }
+                }
+                stack.push(k);
             }
+        }
 
-            if v > max_height {
-                is_visible[i][j] |= true;
-                max_height = v;
-            } else {
-                is_visible[i][j] |= false;
+        sight
+    }
+
+    pub fn survey(&self) -> Survey {
+        let mut total = Survey {
+            visible: vec![vec![false; self.ncols]; self.nrows],
+            scenic: vec![vec![1; self.ncols]; self.nrows],
+        };
+
+        for dir in DIRECTIONS {
+            let sight = self.look(dir);
+            for i in 0..self.nrows {
+                for j in 0..self.ncols {
+                    total.visible[i][j] |= sight.visible[i][j];
+                    total.scenic[i][j] *= sight.distance[i][j];
+                }
             }
         }
+
+        total
     }
+}
 
-    Ok(is_visible.into_iter().flatten().map(|x| x as usize).sum())
+pub fn survey(input: &[u8]) -> Result<Survey> {
+    Ok(parse_input(input)?.survey())
+}
+
+pub fn solve_a() -> Result<usize> {
+    let survey = survey(include_bytes!("../input"))?;
+    Ok(survey.visible.iter().flatten().filter(|&&v| v).count())
 }
 
 pub fn solve_b() -> Result<usize> {
-    let map = parse_input(include_bytes!("../input"));
-    let nrows = map.len();
-    let ncols = map[0].len();
+    let survey = survey(include_bytes!("../input"))?;
+    Ok(survey.scenic.iter().flatten().copied().max().unwrap_or(0))
+}
 
-    let mut max_score = 0usize;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lcg::Lcg;
 
-    for i in 1..nrows - 1 {
-        for j in 1..ncols - 1 {
-            let h = map[i][j];
-            if h <= 1 {
-                continue;
+    // Walks out from every tree in every direction
+    fn survey_naive(forest: &Forest) -> Survey {
+        let (nrows, ncols) = (forest.nrows as i32, forest.ncols as i32);
+        let mut survey = Survey {
+            visible: vec![vec![false; forest.ncols]; forest.nrows],
+            scenic: vec![vec![1; forest.ncols]; forest.nrows],
+        };
+
+        for i in 0..nrows {
+            for j in 0..ncols {
+                let h = forest.heights[i as usize][j as usize];
+                for (di, dj) in [(-1, 0), (1, 0), (0, 1), (0, -1)] {
+                    let (mut y, mut x, mut n) = (i + di, j + dj, 0);
+                    let mut blocked = false;
+                    while (0..nrows).contains(&y) && (0..ncols).contains(&x) {
+                        n += 1;
+                        if forest.heights[y as usize][x as usize] >= h {
+                            blocked = true;
+                            break;
+                        }
+                        y += di;
+                        x += dj;
+                    }
+                    survey.visible[i as usize][j as usize] |= !blocked;
+                    survey.scenic[i as usize][j as usize] *= n;
+                }
             }
-            max_score = max_score.max({
-                // West
-                let w = (1..j + 1)
-                    .map(|offset| map[i][j - offset])
-                    .position(|nh| nh >= h)
-                    .unwrap_or_else(|| j.wrapping_sub(1))
-                    .wrapping_add(1);
-
-                // East
-                let e = (1..(ncols - j))
-                    .map(|offset| map[i][j + offset])
-                    .position(|nh| nh >= h)
-                    .unwrap_or_else(|| (ncols - j).wrapping_sub(2))
-                    .wrapping_add(1);
-
-                // North
-                let n = (1..i + 1)
-                    .map(|offset| map[i - offset][j])
-                    .position(|nh| nh >= h)
-                    .unwrap_or_else(|| i.wrapping_sub(1))
-                    .wrapping_add(1);
-
-                // South
-                let s = (1..(nrows - i))
-                    .map(|offset| map[i + offset][j])
-                    .position(|nh| nh >= h)
-                    .unwrap_or_else(|| (nrows - i).wrapping_sub(2))
-                    .wrapping_add(1);
-
-                n * s * e * w
-            });
         }
+
+        survey
     }
 
-    Ok(max_score)
+    #[test]
+    fn test_example() {
+        let survey = survey(include_bytes!("../example1")).unwrap();
+
+        assert_eq!(survey.visible.iter().flatten().filter(|&&v| v).count(), 21);
+        assert_eq!(survey.scenic[1][2], 4);
+        assert_eq!(survey.scenic[3][2], 8);
+        assert_eq!(survey.scenic.iter().flatten().max(), Some(&8));
+
+        let forest = parse_input(include_bytes!("../example1")).unwrap();
+        let west = forest.look(Direction::West);
+        assert_eq!(west.visible[1], vec![true, true, false, false, false]);
+        assert_eq!(west.distance[3][2], 2);
+
+        assert!(parse_input(b"123\n45\n").is_err());
+        assert!(parse_input(b"12a\n").is_err());
+    }
+
+    #[test]
+    fn test_random_forests() {
+        let mut rng = Lcg::new(7);
+        for (nrows, ncols) in [(1, 1), (1, 7), (6, 1), (9, 13), (20, 20)] {
+            for _ in 0..20 {
+                let heights = (0..nrows)
+                    .map(|_| (0..ncols).map(|_| rng.below(10) as u8).collect())
+                    .collect();
+                let forest = Forest {
+                    heights,
+                    nrows,
+                    ncols,
+                };
+                assert_eq!(forest.survey(), survey_naive(&forest));
+            }
+        }
+    }
 }
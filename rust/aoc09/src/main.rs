@@ -1,8 +1,27 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 
 pub fn main() -> Result<()> {
+    let args = std::env::args().collect::<Vec<_>>();
+    match &args[1..] {
+        [] => {}
+        [cmd, nknots] if cmd == "draw" => {
+            print!("{}", animate(include_str!("../input"), nknots.parse()?)?);
+            return Ok(());
+        }
+        [cmd, nknots, path] if cmd == "heatmap" => {
+            let heatmap = Heatmap::new(include_str!("../input"), nknots.parse()?)?;
+            print!("{}", heatmap.draw());
+            heatmap.write_ppm(path)?;
+            return Ok(());
+        }
+        _ => bail!(
+            "usage: {} [draw <knots> | heatmap <knots> <ppm-file>]",
+            args[0]
+        ),
+    }
+
     let start = std::time::Instant::now();
     let soln_a = solve_a()?;
     eprintln!("Part A elapsed {:?}", start.elapsed());
@@ -16,24 +35,29 @@ pub fn main() -> Result<()> {
     Ok(())
 }
 
-type Motion = ((i32, i32), i32);
+type Motion = ((i32, i32), u32);
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-struct Pos(i32, i32);
+pub struct Pos(pub i32, pub i32);
 
-fn parse_input(input: &str) -> Vec<Motion> {
+fn parse_input(input: &str) -> Result<Vec<Motion>> {
     input
         .lines()
-        .map(|l| l.split_once(' ').unwrap())
-        .map(|(d, n)| {
-            let x = n.parse::<i32>().unwrap();
-            match d {
+        .enumerate()
+        .map(|(i, l)| {
+            let (d, n) = l
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("line {}: expected a direction and a distance", i + 1))?;
+            let x = n
+                .parse::<u32>()
+                .map_err(|e| anyhow!("line {}: bad distance '{}': {}", i + 1, n, e))?;
+            Ok(match d {
                 "L" => ((-1, 0), x),
                 "R" => ((1, 0), x),
                 "U" => ((0, 1), x),
                 "D" => ((0, -1), x),
-                _ => unreachable!(),
-            }
+                _ => bail!("line {}: unknown direction {}", i + 1, d),
+            })
         })
         .collect()
 }
@@ -42,82 +66,345 @@ fn is_touching(h: &Pos, t: &Pos) -> bool {
     h.0.abs_diff(t.0) <= 1 && h.1.abs_diff(t.1) <= 1
 }
 
-pub fn solve_a() -> Result<usize> {
-    let visited = parse_input(include_str!("../input"))
-        .iter()
-        .fold(
-            (Pos(0, 0), Pos(0, 0), HashSet::from([Pos(0, 0)])),
-            |(mut h, mut t, mut visited), (dir, mag)| {
-                let hfinal = Pos(h.0 + dir.0 * mag, h.1 + dir.1 * mag);
-
-                if hfinal.0.abs_diff(t.0) > 1 || hfinal.1.abs_diff(t.1) > 1 {
-                    for _ in 0..*mag {
-                        h.0 += dir.0;
-                        h.1 += dir.1;
+// Which knots to record the visited positions of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Track {
+    Knot(usize),
+    All,
+}
 
-                        if is_touching(&h, &t) {
-                            continue;
-                        }
+// The head is knot 0 and the tail is the last knot
+pub struct Rope {
+    knots: Vec<Pos>,
+    track: Track,
+    visited: Vec<HashSet<Pos>>,
+}
 
-                        let diff = (h.0 - t.0, h.1 - t.1);
+impl Rope {
+    pub fn new(nknots: usize, track: Track) -> Result<Rope> {
+        if nknots == 0 {
+            bail!("a rope needs at least one knot");
+        }
+        if let Track::Knot(k) = track {
+            if k >= nknots {
+                bail!("can't track knot {} of a rope with {} knots", k, nknots);
+            }
+        }
 
-                        t.0 += diff.0.signum();
-                        t.1 += diff.1.signum();
+        let mut rope = Rope {
+            knots: vec![Pos(0, 0); nknots],
+            track,
+            visited: vec![HashSet::new(); nknots],
+        };
+        rope.record();
+        Ok(rope)
+    }
 
-                        visited.insert(t);
-                    }
+    fn record(&mut self) {
+        match self.track {
+            Track::Knot(k) => {
+                self.visited[k].insert(self.knots[k]);
+            }
+            Track::All => {
+                for (visited, knot) in self.visited.iter_mut().zip(&self.knots) {
+                    visited.insert(*knot);
                 }
+            }
+        }
+    }
+
+    // Moves the head one step and lets the rest of the rope follow
+    pub fn step(&mut self, dir: (i32, i32)) {
+        self.knots[0].0 += dir.0;
+        self.knots[0].1 += dir.1;
+
+        for ki in 1..self.knots.len() {
+            let (h, t) = (self.knots[ki - 1], &mut self.knots[ki]);
+
+            // Once a knot stays put, so does everything behind it
+            if is_touching(&h, t) {
+                break;
+            }
+
+            let diff = (h.0 - t.0, h.1 - t.1);
+
+            t.0 += diff.0.signum();
+            t.1 += diff.1.signum();
+        }
+
+        self.record();
+    }
+
+    pub fn knots(&self) -> &[Pos] {
+        &self.knots
+    }
+
+    // Every position the knot has been at, if it's being tracked
+    pub fn visited(&self, knot: usize) -> Option<&HashSet<Pos>> {
+        match self.track {
+            Track::Knot(k) if k != knot => None,
+            _ => self.visited.get(knot),
+        }
+    }
+}
+
+// The knot positions after every single step of the head
+pub fn knot_positions(input: &str, nknots: usize) -> Result<Vec<Vec<Pos>>> {
+    let mut rope = Rope::new(nknots, Track::Knot(0))?;
+    let mut positions = vec![];
+
+    for (dir, mag) in parse_input(input)? {
+        for _ in 0..mag {
+            rope.step(dir);
+            positions.push(rope.knots().to_vec());
+        }
+    }
+
+    Ok(positions)
+}
 
-                (hfinal, t, visited)
+// The number of positions `knot` visits
+pub fn count_visited(input: &str, nknots: usize, knot: usize) -> Result<usize> {
+    let mut rope = Rope::new(nknots, Track::Knot(knot))?;
+
+    for (dir, mag) in parse_input(input)? {
+        for _ in 0..mag {
+            rope.step(dir);
+        }
+    }
+
+    Ok(rope.visited(knot).unwrap().len())
+}
+
+// The smallest rectangle holding the starting position and every given position
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    x: (i32, i32),
+    y: (i32, i32),
+}
+
+impl Bounds {
+    fn of<'a>(positions: impl IntoIterator<Item = &'a Pos>) -> Bounds {
+        positions.into_iter().fold(
+            Bounds {
+                x: (0, 0),
+                y: (0, 0),
+            },
+            |b, p| Bounds {
+                x: (b.x.0.min(p.0), b.x.1.max(p.0)),
+                y: (b.y.0.min(p.1), b.y.1.max(p.1)),
             },
         )
-        .2;
+    }
 
-    let x = visited.len();
+    // Rows from the top, in the same orientation as the puzzle description
+    fn draw(&self, cell: impl Fn(Pos) -> char) -> String {
+        let mut out = String::new();
+        for y in (self.y.0..=self.y.1).rev() {
+            out.extend((self.x.0..=self.x.1).map(|x| cell(Pos(x, y))));
+            out.push('\n');
+        }
+        out
+    }
+}
 
-    Ok(x)
+fn knot_label(k: usize, nknots: usize) -> char {
+    match k {
+        0 => 'H',
+        _ if nknots == 2 => 'T',
+        1..=9 => (b'0' + k as u8) as char,
+        _ => (b'a' + ((k - 10) % 26) as u8) as char,
+    }
 }
 
-pub fn solve_b() -> Result<usize> {
-    let nknots = 10;
-
-    let visited = parse_input(include_str!("../input"))
-        .iter()
-        .fold(
-            (vec![Pos(0, 0); nknots], HashSet::from([Pos(0, 0)])),
-            |(mut knots, mut visited), (dir, mag)| {
-                for _ in 0..*mag {
-                    knots[0].0 += dir.0;
-                    knots[0].1 += dir.1;
-
-                    for ki in 1..nknots {
-                        let (h, t) = knots.split_at_mut(ki);
-                        let (h, t) = (h[ki - 1], &mut t[0]);
-
-                        if h.0.abs_diff(t.0) > 1 || h.1.abs_diff(t.1) > 1 {
-                            if is_touching(&h, t) {
-                                continue;
-                            }
-
-                            let diff = (h.0 - t.0, h.1 - t.1);
-
-                            t.0 += diff.0.signum();
-                            t.1 += diff.1.signum();
-
-                            if ki == 9 {
-                                visited.insert(*t);
-                            }
-                        } else {
-                            break;
-                        }
+// Draws the rope like the puzzle description: knots nearer the head cover the ones behind, and
+// an uncovered starting position is marked with 's'
+fn draw_rope(knots: &[Pos], bounds: Bounds) -> String {
+    bounds.draw(|p| match knots.iter().position(|&k| k == p) {
+        Some(k) => knot_label(k, knots.len()),
+        None if p == Pos(0, 0) => 's',
+        None => '.',
+    })
+}
+
+// The rope after each motion, on a grid big enough for the whole simulation
+pub fn animate(input: &str, nknots: usize) -> Result<String> {
+    let motions = parse_input(input)?;
+    let positions = knot_positions(input, nknots)?;
+    let bounds = Bounds::of(positions.iter().flatten());
+
+    let mut out = String::new();
+    let mut step = 0;
+    for (line, (_, mag)) in input.lines().zip(motions) {
+        step += mag as usize;
+        let knots = match step {
+            0 => vec![Pos(0, 0); nknots],
+            _ => positions[step - 1].clone(),
+        };
+        out.push_str(&format!(
+            "== {} ==\n\n{}\n",
+            line,
+            draw_rope(&knots, bounds)
+        ));
+    }
+
+    Ok(out)
+}
+
+// How many times the tail arrived at each position, counting the start as one arrival. The
+// grid covers the whole rope so it lines up with `animate`.
+pub struct Heatmap {
+    counts: HashMap<Pos, usize>,
+    bounds: Bounds,
+}
+
+impl Heatmap {
+    pub fn new(input: &str, nknots: usize) -> Result<Heatmap> {
+        let positions = knot_positions(input, nknots)?;
+
+        let mut counts = HashMap::from([(Pos(0, 0), 1)]);
+        let mut tail = Pos(0, 0);
+        for knots in &positions {
+            let t = knots[nknots - 1];
+            if t != tail {
+                *counts.entry(t).or_insert(0) += 1;
+                tail = t;
+            }
+        }
+
+        let bounds = Bounds::of(positions.iter().flatten());
+        Ok(Heatmap { counts, bounds })
+    }
+
+    // Every position the tail visited, in the same format as the puzzle description
+    pub fn draw(&self) -> String {
+        self.bounds.draw(|p| match self.counts.contains_key(&p) {
+            _ if p == Pos(0, 0) => 's',
+            true => '#',
+            false => '.',
+        })
+    }
+
+    pub fn write_ppm(&self, path: &str) -> Result<()> {
+        std::fs::write(path, self.ppm())?;
+
+        Ok(())
+    }
+
+    // A binary PPM where brighter cells were visited more often, on a log scale
+    fn ppm(&self) -> Vec<u8> {
+        const CELL: i32 = 4;
+
+        let max = self.counts.values().copied().max().unwrap_or(1) as f64;
+        let (w, h) = (
+            (self.bounds.x.1 - self.bounds.x.0 + 1) * CELL,
+            (self.bounds.y.1 - self.bounds.y.0 + 1) * CELL,
+        );
+
+        let mut out = format!("P6\n{} {}\n255\n", w, h).into_bytes();
+        for py in 0..h {
+            for px in 0..w {
+                let p = Pos(self.bounds.x.0 + px / CELL, self.bounds.y.1 - py / CELL);
+                match self.counts.get(&p) {
+                    _ if p == Pos(0, 0) => out.extend_from_slice(&[200, 60, 60]),
+                    Some(&c) => {
+                        let v = 60.0 + 195.0 * (1.0 + c as f64).ln() / (1.0 + max).ln();
+                        out.extend_from_slice(&[v as u8, v as u8, 90]);
                     }
+                    None => out.extend_from_slice(&[15, 15, 35]),
                 }
-                (knots, visited)
-            },
-        )
-        .1;
+            }
+        }
+
+        out
+    }
+}
+
+pub fn solve_a() -> Result<usize> {
+    count_visited(include_str!("../input"), 2, 1)
+}
+
+pub fn solve_b() -> Result<usize> {
+    count_visited(include_str!("../input"), 10, 9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_examples() {
+        let example1 = include_str!("../example1");
+        let example2 = include_str!("../example2");
+
+        assert_eq!(count_visited(example1, 2, 1).unwrap(), 13);
+        assert_eq!(count_visited(example1, 10, 9).unwrap(), 1);
+        assert_eq!(count_visited(example2, 10, 9).unwrap(), 36);
+
+        assert!(Rope::new(0, Track::All).is_err());
+        assert!(Rope::new(2, Track::Knot(2)).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let err = parse_input("R 4\nU -4\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2: bad distance '-4': invalid digit found in string"
+        );
+        assert!(count_visited("R 4\nL x\n", 2, 1).is_err());
+    }
+
+    #[test]
+    fn test_positions() {
+        let example1 = include_str!("../example1");
+
+        // R 4 then the first step of U 4
+        let positions = knot_positions(example1, 2).unwrap();
+        assert_eq!(positions[3], vec![Pos(4, 0), Pos(3, 0)]);
+        assert_eq!(positions[4], vec![Pos(4, 1), Pos(3, 0)]);
+        assert_eq!(positions[5], vec![Pos(4, 2), Pos(4, 1)]);
+
+        // Tracking every knot agrees with tracking them one at a time
+        let example2 = include_str!("../example2");
+        let mut rope = Rope::new(10, Track::All).unwrap();
+        for (dir, mag) in parse_input(example2).unwrap() {
+            for _ in 0..mag {
+                rope.step(dir);
+            }
+        }
+        for k in 0..10 {
+            assert_eq!(
+                rope.visited(k).unwrap().len(),
+                count_visited(example2, 10, k).unwrap()
+            );
+        }
+
+        let mut rope = Rope::new(1, Track::Knot(0)).unwrap();
+        rope.step((1, 0));
+        assert_eq!(rope.knots(), &[Pos(1, 0)]);
+        assert_eq!(rope.visited(0).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_draw() {
+        let example1 = include_str!("../example1");
+
+        let frames = animate(example1, 2).unwrap();
+        assert!(frames.starts_with("== R 4 ==\n\n......\n......\n......\n......\ns..TH.\n\n"));
+        assert!(frames.ends_with("== R 2 ==\n\n......\n......\n.TH...\n......\ns.....\n\n"));
+
+        let frames = animate(example1, 10).unwrap();
+        assert!(frames.contains("== U 4 ==\n\n....H.\n....1.\n..432.\n.5....\n6.....\n\n"));
 
-    let x = visited.len();
+        let heatmap = Heatmap::new(example1, 2).unwrap();
+        assert_eq!(heatmap.draw(), "..##..\n...##.\n.####.\n....#.\ns###..\n");
 
-    Ok(x)
+        // 6x5 cells, with the start in the bottom left corner
+        let ppm = heatmap.ppm();
+        let header = "P6\n24 20\n255\n";
+        assert!(ppm.starts_with(header.as_bytes()));
+        assert_eq!(ppm.len(), header.len() + 24 * 20 * 3);
+        assert_eq!(ppm[ppm.len() - 24 * 3..][..3], [200, 60, 60]);
+    }
 }
@@ -1,6 +1,7 @@
 use std::collections::VecDeque;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use cycle::Cycle;
 
 pub fn main() -> Result<()> {
     let start = std::time::Instant::now();
@@ -16,151 +17,565 @@ pub fn main() -> Result<()> {
     Ok(())
 }
 
-#[derive(Debug)]
-enum Operation {
-    Add(usize),
-    Multiply(usize),
-    Square,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+impl BinOp {
+    fn symbol(&self) -> char {
+        match self {
+            BinOp::Add => '+',
+            BinOp::Sub => '-',
+            BinOp::Mul => '*',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Old,
+    Const(usize),
+    Op(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, old: usize) -> Result<usize> {
+        match self {
+            Expr::Old => Ok(old),
+            Expr::Const(x) => Ok(*x),
+            Expr::Op(op, lhs, rhs) => {
+                let (a, b) = (lhs.eval(old)?, rhs.eval(old)?);
+                let result = match op {
+                    BinOp::Add => a.checked_add(b),
+                    BinOp::Sub => a.checked_sub(b),
+                    BinOp::Mul => a.checked_mul(b),
+                };
+                result
+                    .ok_or_else(|| anyhow!("{} {} {} overflows (old = {})", a, op.symbol(), b, old))
+            }
+        }
+    }
+
+    // Evaluates modulo `m`, widening to u128 so intermediate products can't overflow
+    fn eval_mod(&self, old: usize, m: usize) -> usize {
+        self.eval_wide(old as u128 % m as u128, m as u128) as usize
+    }
+
+    fn eval_wide(&self, old: u128, m: u128) -> u128 {
+        match self {
+            Expr::Old => old,
+            Expr::Const(x) => *x as u128 % m,
+            Expr::Op(op, lhs, rhs) => {
+                let (a, b) = (lhs.eval_wide(old, m), rhs.eval_wide(old, m));
+                match op {
+                    BinOp::Add => (a + b) % m,
+                    BinOp::Sub => (a + m - b) % m,
+                    BinOp::Mul => (a * b) % m,
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expr::Old => write!(f, "old"),
+            Expr::Const(x) => write!(f, "{}", x),
+            Expr::Op(op, lhs, rhs) => write!(f, "({} {} {})", lhs, op.symbol(), rhs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Old,
+    Num(usize),
+    Op(BinOp),
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' => {
+                chars.next();
+            }
+            '+' | '-' | '*' | '(' | ')' => {
+                chars.next();
+                tokens.push(match c {
+                    '+' => Token::Op(BinOp::Add),
+                    '-' => Token::Op(BinOp::Sub),
+                    '*' => Token::Op(BinOp::Mul),
+                    '(' => Token::Open,
+                    _ => Token::Close,
+                });
+            }
+            _ if c.is_ascii_alphanumeric() => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric()) {
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(match word.as_str() {
+                    "old" => Token::Old,
+                    _ => Token::Num(
+                        word.parse()
+                            .map_err(|_| anyhow!("unexpected '{}' in operation", word))?,
+                    ),
+                });
+            }
+            _ => bail!("unexpected '{}' in operation", c),
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Recursive descent over `expr := term (('+' | '-') term)*`, `term := atom ('*' atom)*`
+// and `atom := 'old' | number | '(' expr ')'`
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.term()?;
+        while let Some(&Token::Op(op @ (BinOp::Add | BinOp::Sub))) = self.peek() {
+            self.pos += 1;
+            lhs = Expr::Op(op, Box::new(lhs), Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let mut lhs = self.atom()?;
+        while let Some(&Token::Op(BinOp::Mul)) = self.peek() {
+            self.pos += 1;
+            lhs = Expr::Op(BinOp::Mul, Box::new(lhs), Box::new(self.atom()?));
+        }
+        Ok(lhs)
+    }
+
+    fn atom(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Old) => Ok(Expr::Old),
+            Some(Token::Num(x)) => Ok(Expr::Const(x)),
+            Some(Token::Open) => {
+                let e = self.expr()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(e),
+                    _ => bail!("expected ')' in operation"),
+                }
+            }
+            Some(t) => bail!("unexpected {:?} in operation", t),
+            None => bail!("operation ends unexpectedly"),
+        }
+    }
+}
+
+fn parse_expr(s: &str) -> Result<Expr> {
+    let mut parser = Parser {
+        tokens: tokenize(s)?,
+        pos: 0,
+    };
+    let e = parser.expr()?;
+    if parser.pos != parser.tokens.len() {
+        bail!("trailing input in operation '{}'", s);
+    }
+    Ok(e)
 }
 
 type MonkeyIndex = usize;
+type ItemIndex = usize;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Monkey {
-    items: VecDeque<usize>,
-    op: Operation,
+    items: VecDeque<(ItemIndex, usize)>,
+    op: Expr,
     test_div_by: usize,
     ttt: MonkeyIndex,
     ttf: MonkeyIndex,
-    ninspected: usize,
 }
 
-fn parse_ending_int(line: &str) -> usize {
+impl Monkey {
+    fn target(&self, worry: usize) -> MonkeyIndex {
+        if worry.is_multiple_of(self.test_div_by) {
+            self.ttt
+        } else {
+            self.ttf
+        }
+    }
+}
+
+fn parse_ending_int(line: &str) -> Result<usize> {
     line.split_ascii_whitespace()
         .last()
-        .unwrap()
+        .ok_or_else(|| anyhow!("empty line"))?
         .parse::<usize>()
-        .unwrap()
+        .map_err(|e| anyhow!("'{}': {}", line.trim(), e))
 }
 
-fn parse_starting_items(line: &str) -> VecDeque<usize> {
+fn parse_starting_items(line: &str) -> Result<Vec<usize>> {
     line.split_once(':')
-        .unwrap()
+        .ok_or_else(|| anyhow!("expected starting items, got '{}'", line.trim()))?
         .1
         .split(',')
-        .map(|x| x.trim().parse::<usize>().unwrap())
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| Ok(x.trim().parse::<usize>()?))
         .collect()
 }
 
-fn parse_operation(line: &str) -> Operation {
-    let p = line
+fn parse_operation(line: &str) -> Result<Expr> {
+    let rhs = line
         .split_once('=')
-        .unwrap()
-        .1
-        .trim()
-        .splitn(3, ' ')
-        .collect::<Vec<_>>();
-
-    match (p[1], p[2]) {
-        ("*", "old") => Operation::Square,
-        ("+", x) => Operation::Add(x.parse::<usize>().unwrap()),
-        ("*", x) => Operation::Multiply(x.parse::<usize>().unwrap()),
-        _ => unreachable!(),
-    }
+        .ok_or_else(|| anyhow!("expected operation, got '{}'", line.trim()))?
+        .1;
+    parse_expr(rhs.trim())
 }
 
-fn parse_input(input: &str) -> Vec<Monkey> {
-    input
+fn parse_input(input: &str) -> Result<Vec<Monkey>> {
+    let mut monkeys = input
         .split("\n\n")
-        .map(|block| {
-            let lines = block.splitn(6, '\n').collect::<Vec<_>>();
-
-            let items = parse_starting_items(lines[1]);
-            let op = parse_operation(lines[2]);
-            let test_div_by = parse_ending_int(lines[3]);
-            let ttt = parse_ending_int(lines[4]);
-            let ttf = parse_ending_int(lines[5]);
-
-            Monkey {
-                items,
-                op,
-                test_div_by,
-                ttt,
-                ttf,
-                ninspected: 0,
+        .filter(|block| !block.trim().is_empty())
+        .enumerate()
+        .map(|(i, block)| {
+            let lines = block.trim().lines().collect::<Vec<_>>();
+            if lines.len() != 6 {
+                bail!("monkey {}: expected 6 lines, got {}", i, lines.len());
             }
+
+            let parse = || -> Result<Monkey> {
+                Ok(Monkey {
+                    items: parse_starting_items(lines[1])?
+                        .into_iter()
+                        .map(|worry| (0, worry))
+                        .collect(),
+                    op: parse_operation(lines[2])?,
+                    test_div_by: parse_ending_int(lines[3])?,
+                    ttt: parse_ending_int(lines[4])?,
+                    ttf: parse_ending_int(lines[5])?,
+                })
+            };
+            parse().map_err(|e| anyhow!("monkey {}: {}", i, e))
         })
-        .collect()
+        .collect::<Result<Vec<_>>>()?;
+
+    // Items are numbered in the order they first appear
+    let mut next_item = 0;
+    for m in monkeys.iter_mut() {
+        for (id, _) in m.items.iter_mut() {
+            *id = next_item;
+            next_item += 1;
+        }
+    }
+
+    for (i, m) in monkeys.iter().enumerate() {
+        if m.test_div_by == 0 {
+            bail!("monkey {}: can't test divisibility by 0", i);
+        }
+        for target in [m.ttt, m.ttf] {
+            if target >= monkeys.len() || target == i {
+                bail!("monkey {}: can't throw to monkey {}", i, target);
+            }
+        }
+    }
+
+    Ok(monkeys)
 }
 
-pub fn solve_a() -> Result<usize> {
-    let nrounds = 20;
+// How worry levels are kept in check after each inspection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relief {
+    // Worry is divided by the given amount, rounding down
+    Divide(usize),
+    // Worry is reduced modulo the lcm of every monkey's divisor, which doesn't change the
+    // outcome of any test
+    Modulo,
+}
+
+// `Relief` with the modulus filled in
+#[derive(Debug, Clone, Copy)]
+enum Reduction {
+    Divide(usize),
+    Modulo(usize),
+}
 
-    let mut monkeys = parse_input(include_str!("../input"));
+fn gcd(a: usize, b: usize) -> usize {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+fn lcm(monkeys: &[Monkey]) -> Result<usize> {
+    monkeys.iter().try_fold(1, |acc, m| {
+        (acc / gcd(acc, m.test_div_by))
+            .checked_mul(m.test_div_by)
+            .ok_or_else(|| anyhow!("lcm of the test divisors overflows"))
+    })
+}
+
+// One inspection of an item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visit {
+    pub round: usize,
+    pub monkey: MonkeyIndex,
+    // Worry level as the monkey picks the item up
+    pub worry: usize,
+}
+
+// Runs `rounds` rounds, returning how many items each monkey inspected in each round.
+// `on_visit` is called with every inspection of every item.
+fn simulate(
+    mut monkeys: Vec<Monkey>,
+    rounds: usize,
+    relief: Relief,
+    mut on_visit: impl FnMut(ItemIndex, Visit),
+) -> Result<Vec<Vec<usize>>> {
+    // The lcm is only worked out when it's needed, since it may not fit in a usize even when
+    // dividing would work fine
+    let reduction = match relief {
+        Relief::Divide(0) => bail!("can't divide worry levels by 0"),
+        Relief::Divide(d) => Reduction::Divide(d),
+        Relief::Modulo => Reduction::Modulo(lcm(&monkeys)?),
+    };
+
+    let mut counts = vec![];
+
+    for r in 0..rounds {
+        let mut round = vec![0; monkeys.len()];
 
-    for _ in 0..nrounds {
         for mi in 0..monkeys.len() {
-            while let Some(worry) = monkeys[mi].items.pop_front() {
-                let m = &mut monkeys[mi];
-                let new_worry = match m.op {
-                    Operation::Add(x) => (worry + x) / 3,
-                    Operation::Multiply(x) => (worry * x) / 3,
-                    Operation::Square => (worry * worry) / 3,
-                };
-                m.ninspected += 1;
+            while let Some((item, worry)) = monkeys[mi].items.pop_front() {
+                on_visit(
+                    item,
+                    Visit {
+                        round: r,
+                        monkey: mi,
+                        worry,
+                    },
+                );
 
-                let target_mi = if new_worry % m.test_div_by == 0 {
-                    m.ttt
-                } else {
-                    m.ttf
+                let m = &monkeys[mi];
+                let new_worry = match reduction {
+                    Reduction::Divide(d) => m.op.eval(worry)? / d,
+                    Reduction::Modulo(modulus) => m.op.eval_mod(worry, modulus),
                 };
+                round[mi] += 1;
 
-                monkeys[target_mi].items.push_back(new_worry);
+                let target_mi = m.target(new_worry);
+                monkeys[target_mi].items.push_back((item, new_worry));
             }
         }
+
+        counts.push(round);
+    }
+
+    Ok(counts)
+}
+
+pub fn inspection_counts(input: &str, rounds: usize, relief: Relief) -> Result<Vec<Vec<usize>>> {
+    simulate(parse_input(input)?, rounds, relief, |_, _| {})
+}
+
+// Every inspection of every item over `rounds` rounds, indexed by item in the order the items
+// first appear in the input
+pub fn item_paths(input: &str, rounds: usize, relief: Relief) -> Result<Vec<Vec<Visit>>> {
+    let monkeys = parse_input(input)?;
+    let nitems = monkeys.iter().map(|m| m.items.len()).sum();
+
+    let mut paths = vec![vec![]; nitems];
+    simulate(monkeys, rounds, relief, |item, visit| {
+        paths[item].push(visit)
+    })?;
+
+    Ok(paths)
+}
+
+// Under `Relief::Modulo` an item's path only depends on which monkey holds it and its worry
+// level, so every item eventually cycles. Returns the (monkey, worry) at each inspection up to
+// the end of the first pass through the cycle.
+pub fn item_cycle(input: &str, item: ItemIndex) -> Result<(Vec<(MonkeyIndex, usize)>, Cycle)> {
+    let monkeys = parse_input(input)?;
+    let modulus = lcm(&monkeys)?;
+
+    let start = monkeys
+        .iter()
+        .enumerate()
+        .find_map(|(mi, m)| {
+            m.items
+                .iter()
+                .find(|&&(id, _)| id == item)
+                .map(|&(_, worry)| (mi, worry % modulus))
+        })
+        .ok_or_else(|| anyhow!("there is no item {}", item))?;
+
+    Ok(cycle::detect_states(start, |&(mi, worry)| {
+        let new_worry = monkeys[mi].op.eval_mod(worry, modulus);
+        (monkeys[mi].target(new_worry), new_worry)
+    }))
+}
+
+pub fn monkey_business(input: &str, rounds: usize, relief: Relief) -> Result<usize> {
+    let counts = inspection_counts(input, rounds, relief)?;
+
+    let mut totals = vec![0; counts.first().map_or(0, |r| r.len())];
+    for round in counts {
+        for (total, n) in totals.iter_mut().zip(round) {
+            *total += n;
+        }
     }
 
-    monkeys.sort_unstable_by_key(|e| e.ninspected);
+    totals.sort_unstable();
 
-    let monkey_business =
-        monkeys[monkeys.len() - 1].ninspected * monkeys[monkeys.len() - 2].ninspected;
+    Ok(totals.iter().rev().take(2).product())
+}
 
-    Ok(monkey_business)
+pub fn solve_a() -> Result<usize> {
+    monkey_business(include_str!("../input"), 20, Relief::Divide(3))
 }
 
 pub fn solve_b() -> Result<usize> {
-    let nrounds = 10_000;
+    monkey_business(include_str!("../input"), 10_000, Relief::Modulo)
+}
 
-    let mut monkeys = parse_input(include_str!("../input"));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let common_multiple = monkeys.iter().map(|m| m.test_div_by).product::<usize>();
+    #[test]
+    fn test_parse_expr() {
+        assert_eq!(parse_expr("old * old").unwrap().to_string(), "(old * old)");
+        assert_eq!(
+            parse_expr("old + 3 * old - 2").unwrap().to_string(),
+            "((old + (3 * old)) - 2)"
+        );
+        assert_eq!(
+            parse_expr("(old+1)*(old-1)").unwrap().to_string(),
+            "((old + 1) * (old - 1))"
+        );
 
-    for _ in 0..nrounds {
-        for mi in 0..monkeys.len() {
-            while let Some(worry) = monkeys[mi].items.pop_front() {
-                let m = &mut monkeys[mi];
-                let new_worry = match m.op {
-                    Operation::Add(x) => (worry + x) % common_multiple,
-                    Operation::Multiply(x) => (worry * x) % common_multiple,
-                    Operation::Square => (worry * worry) % common_multiple,
-                };
-                m.ninspected += 1;
+        assert!(parse_expr("old / 2").is_err());
+        assert!(parse_expr("old +").is_err());
+        assert!(parse_expr("(old").is_err());
+        assert!(parse_expr("old old").is_err());
 
-                let target_mi = if new_worry % m.test_div_by == 0 {
-                    m.ttt
-                } else {
-                    m.ttf
-                };
+        let e = parse_expr("old * old - 3 * old + 7").unwrap();
+        for old in [3, 10, 12345] {
+            let expected = old * old - 3 * old + 7;
+            assert_eq!(e.eval(old).unwrap(), expected);
+            assert_eq!(e.eval_mod(old, 97), expected % 97);
+        }
+        assert!(parse_expr("old - 5").unwrap().eval(3).is_err());
+        assert_eq!(parse_expr("old - 5").unwrap().eval_mod(3, 7), 5);
+    }
 
-                monkeys[target_mi].items.push_back(new_worry);
-            }
+    #[test]
+    fn test_example() {
+        let input = include_str!("../example1");
+
+        assert_eq!(
+            monkey_business(input, 20, Relief::Divide(3)).unwrap(),
+            10605
+        );
+        assert_eq!(
+            monkey_business(input, 10_000, Relief::Modulo).unwrap(),
+            2713310158
+        );
+
+        let counts = inspection_counts(input, 20, Relief::Modulo).unwrap();
+        assert_eq!(counts[0], vec![2, 4, 3, 6]);
+
+        let totals = (0..4)
+            .map(|m| counts.iter().map(|r| r[m]).sum::<usize>())
+            .collect::<Vec<_>>();
+        assert_eq!(totals, vec![99, 97, 8, 103]);
+    }
+
+    #[test]
+    fn test_lcm() {
+        let input = include_str!("../example1").replace("divisible by 13", "divisible by 46");
+        assert_eq!(
+            lcm(&parse_input(&input).unwrap()).unwrap(),
+            23 * 19 * 2 * 17
+        );
+
+        // Without relief the worry levels grow too quickly to get far, but as long as they
+        // don't overflow the reduced simulation must agree
+        for rounds in 1..5 {
+            let exact = inspection_counts(&input, rounds, Relief::Divide(1)).unwrap();
+            let reduced = inspection_counts(&input, rounds, Relief::Modulo).unwrap();
+            assert_eq!(exact, reduced);
         }
+
+        // Three divisors near 2^32 have an lcm beyond a u64, which only matters when reducing
+        let input = include_str!("../example1")
+            .replace("divisible by 23", "divisible by 4294967291")
+            .replace("divisible by 19", "divisible by 4294967279")
+            .replace("divisible by 13", "divisible by 4294967231");
+        assert!(inspection_counts(&input, 20, Relief::Divide(3)).is_ok());
+        let err = inspection_counts(&input, 20, Relief::Modulo).unwrap_err();
+        assert_eq!(err.to_string(), "lcm of the test divisors overflows");
+    }
+
+    #[test]
+    fn test_divide_by_zero() {
+        let input = include_str!("../example1");
+        let err = monkey_business(input, 20, Relief::Divide(0)).unwrap_err();
+        assert_eq!(err.to_string(), "can't divide worry levels by 0");
+        assert!(item_paths(input, 0, Relief::Divide(0)).is_err());
     }
 
-    monkeys.sort_unstable_by_key(|e| e.ninspected);
+    #[test]
+    fn test_items() {
+        let input = include_str!("../example1");
 
-    let monkey_business =
-        monkeys[monkeys.len() - 1].ninspected * monkeys[monkeys.len() - 2].ninspected;
+        // The first item starts at monkey 0 with worry 79 and ends round 1 at monkey 3
+        let paths = item_paths(input, 1, Relief::Divide(3)).unwrap();
+        assert_eq!(paths.len(), 10);
+        assert_eq!(
+            paths[0],
+            vec![
+                Visit {
+                    round: 0,
+                    monkey: 0,
+                    worry: 79
+                },
+                Visit {
+                    round: 0,
+                    monkey: 3,
+                    worry: 500
+                }
+            ]
+        );
 
-    Ok(monkey_business)
+        let rounds = 200;
+        let paths = item_paths(input, rounds, Relief::Modulo).unwrap();
+        for (item, path) in paths.iter().enumerate() {
+            let (states, cycle) = item_cycle(input, item).unwrap();
+            for (i, visit) in path.iter().enumerate() {
+                let (monkey, worry) = states[cycle.index(i)];
+                assert_eq!((visit.monkey, visit.worry), (monkey, worry));
+            }
+        }
+    }
 }
@@ -1,107 +1,180 @@
-use std::collections::HashMap;
-
-use anyhow::Result;
+use anyhow::{bail, Result};
+use cycle::Cycle;
 
 pub fn main() -> Result<()> {
+    let args = std::env::args().collect::<Vec<_>>();
+    let (config, fingerprint, verify_periods) = match &args[1..] {
+        [] => (Config::default(), DEFAULT_FINGERPRINT, 1),
+        [cmd, fingerprint, periods] if cmd == "cycle" => {
+            (Config::default(), fingerprint.parse()?, periods.parse()?)
+        }
+        [cmd, nrocks, nrows] if cmd == "draw" => {
+            print!(
+                "{}",
+                render_tower(&Config::default(), nrocks.parse()?, nrows.parse()?)
+            );
+            return Ok(());
+        }
+        [cmd, njets, nrows] if cmd == "jets" => {
+            print!(
+                "{}",
+                render_tower_after_jets(&Config::default(), njets.parse()?, nrows.parse()?)
+            );
+            return Ok(());
+        }
+        [cmd, nrocks, path] if cmd == "ppm" => {
+            write_tower_ppm(&Config::default(), nrocks.parse()?, path)?;
+            return Ok(());
+        }
+        [rocks, width] => (
+            Config::new(
+                width.parse()?,
+                parse_rocks(&std::fs::read_to_string(rocks)?)?,
+            )?,
+            DEFAULT_FINGERPRINT,
+            1,
+        ),
+        _ => bail!(
+            "usage: {} [<rocks-file> <chamber-width> | cycle <ceiling|top:<rows>|reachable> <verify-periods> | draw <rocks> <rows> | jets <jets> <rows> | ppm <rocks> <ppm-file>]",
+            args[0]
+        ),
+    };
+
     let start = std::time::Instant::now();
-    let soln_a = solve_a()?;
+    let soln_a = solve_a_with(&config)?;
     eprintln!("Part A elapsed {:?}", start.elapsed());
     println!("solution part A: {}", soln_a);
 
     let start = std::time::Instant::now();
-    let soln_b = solve_b()?;
+    let (soln_b, cycle) = solve_b_with(&config, fingerprint, verify_periods)?;
     eprintln!("Part B elapsed {:?}", start.elapsed());
+    if let Some(c) = cycle {
+        eprintln!(
+            "Part B cycle of {} rocks after an offset of {} rocks",
+            c.length,
+            c.start + 1
+        );
+    }
     println!("solution part B: {}", soln_b);
 
     Ok(())
 }
 
-type Row = [bool; 7];
-const MAX_H: usize = 6000;
+type Row = u8;
 
-#[derive(Debug, Clone, Copy)]
-enum Shape {
-    Hbar,
-    Cross,
-    FlipL,
-    Vbar,
-    Box,
+// The mask for column x, counting from the left
+fn column_bit(x: usize) -> Row {
+    1 << (Row::BITS as usize - 1 - x)
+}
+
+// Rock rows run bottom to top with the leftmost column in the high bit. Chamber rows use the same
+// layout, so a rock at column px collides with a row when `(rock_row >> px) & row != 0`.
+#[derive(Debug, Clone)]
+pub struct Rock {
+    rows: Vec<Row>,
+    width: usize,
 }
 
-impl Shape {
+impl Rock {
+    // Rows may be given anywhere in a `Row`; they're shifted so the rock touches the left edge,
+    // and empty rows above and below it are dropped so they don't add to its height
+    pub fn new(rows: Vec<Row>) -> Result<Rock> {
+        let (Some(bottom), Some(top)) = (
+            rows.iter().position(|r| *r != 0),
+            rows.iter().rposition(|r| *r != 0),
+        ) else {
+            bail!("rock has no occupied cells");
+        };
+
+        let rows = &rows[bottom..=top];
+        let shift = rows.iter().map(|r| r.leading_zeros()).min().unwrap();
+        let rows = rows.iter().map(|r| r << shift).collect::<Vec<_>>();
+        let width = rows
+            .iter()
+            .map(|r| (Row::BITS - r.trailing_zeros()) as usize)
+            .max()
+            .unwrap();
+
+        Ok(Rock { rows, width })
+    }
+
     fn height(&self) -> usize {
-        match self {
-            Shape::Hbar => 1,
-            Shape::Cross => 3,
-            Shape::FlipL => 3,
-            Shape::Vbar => 4,
-            Shape::Box => 2,
-        }
+        self.rows.len()
     }
 
-    fn side_collision(&self, m: &Move, ph: usize, px: usize, chamber: &[Row]) -> bool {
-        match (self, m) {
-            (_, Move::Left) if px == 0 => true,
-            (Shape::Hbar, Move::Left) => chamber[ph][px - 1],
-            (Shape::Hbar, Move::Right) => px == 3 || chamber[ph][px + 4],
-            (Shape::Box, Move::Left) => chamber[ph][px - 1] || chamber[ph + 1][px - 1],
-            (Shape::Box, Move::Right) => px == 5 || chamber[ph][px + 2] || chamber[ph + 1][px + 2],
-            (Shape::Vbar, Move::Left) => {
-                chamber[ph][px - 1]
-                    || chamber[ph + 1][px - 1]
-                    || chamber[ph + 2][px - 1]
-                    || chamber[ph + 3][px - 1]
-            }
-            (Shape::Vbar, Move::Right) => {
-                px == 6
-                    || chamber[ph][px + 1]
-                    || chamber[ph + 1][px + 1]
-                    || chamber[ph + 2][px + 1]
-                    || chamber[ph + 3][px + 1]
-            }
-            (Shape::FlipL, Move::Left) => {
-                chamber[ph][px - 1] || chamber[ph + 1][px + 1] || chamber[ph + 2][px + 1]
-            }
-            (Shape::FlipL, Move::Right) => {
-                px == 4 || chamber[ph][px + 3] || chamber[ph + 1][px + 3] || chamber[ph + 2][px + 3]
-            }
-            (Shape::Cross, Move::Left) => {
-                chamber[ph][px] || chamber[ph + 1][px - 1] || chamber[ph + 2][px]
-            }
-            (Shape::Cross, Move::Right) => {
-                px == 4 || chamber[ph][px + 2] || chamber[ph + 1][px + 3] || chamber[ph + 2][px + 2]
-            }
+    fn collides(&self, chamber: &[Row], ph: usize, px: usize) -> bool {
+        self.rows
+            .iter()
+            .enumerate()
+            .any(|(i, r)| chamber[ph + i] & (r >> px) != 0)
+    }
+
+    fn settle(&self, chamber: &mut [Row], ph: usize, px: usize) {
+        for (i, r) in self.rows.iter().enumerate() {
+            chamber[ph + i] |= r >> px;
         }
     }
+}
 
-    fn bottom_collision(&self, ph: usize, px: usize, chamber: &[Row]) -> bool {
-        match self {
-            Shape::Hbar => {
-                ph == 0
-                    || chamber[ph - 1][px]
-                    || chamber[ph - 1][px + 1]
-                    || chamber[ph - 1][px + 2]
-                    || chamber[ph - 1][px + 3]
-            }
-            Shape::Vbar => ph == 0 || chamber[ph - 1][px],
-            Shape::FlipL => {
-                ph == 0 || chamber[ph - 1][px] || chamber[ph - 1][px + 1] || chamber[ph - 1][px + 2]
-            }
-            Shape::Box => ph == 0 || chamber[ph - 1][px] || chamber[ph - 1][px + 1],
-            Shape::Cross => {
-                ph == 0 || chamber[ph - 1][px + 1] || chamber[ph][px] || chamber[ph][px + 2]
-            }
+#[derive(Debug, Clone)]
+pub struct Config {
+    width: usize,
+    rocks: Vec<Rock>,
+}
+
+impl Config {
+    pub fn new(width: usize, rocks: Vec<Rock>) -> Result<Config> {
+        if width == 0 || width > Row::BITS as usize {
+            bail!("chamber width must be between 1 and {}", Row::BITS);
+        }
+
+        if rocks.is_empty() {
+            bail!("no rocks defined");
+        }
+
+        if let Some(r) = rocks.iter().find(|r| r.width + 2 > width) {
+            bail!(
+                "rock of width {} does not fit a chamber of width {}",
+                r.width,
+                width
+            );
         }
+
+        Ok(Config { width, rocks })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::new(7, parse_rocks(include_str!("../rocks")).unwrap()).unwrap()
     }
 }
 
-const SHAPES: [Shape; 5] = [
-    Shape::Hbar,
-    Shape::Cross,
-    Shape::FlipL,
-    Shape::Vbar,
-    Shape::Box,
-];
+pub fn parse_rocks(input: &str) -> Result<Vec<Rock>> {
+    input
+        .split("\n\n")
+        .filter(|block| !block.trim().is_empty())
+        .map(|block| {
+            let rows = block
+                .lines()
+                .rev()
+                .map(|line| {
+                    line.trim_end()
+                        .chars()
+                        .enumerate()
+                        .try_fold(0, |row, (x, c)| match (c, x < Row::BITS as usize) {
+                            ('.', true) => Ok(row),
+                            ('#', true) => Ok(row | column_bit(x)),
+                            (_, false) => bail!("rock row '{}' is too wide", line),
+                            _ => bail!("unexpected character '{}' in rock definition", c),
+                        })
+                })
+                .collect::<Result<Vec<Row>>>()?;
+
+            Rock::new(rows)
+        })
+        .collect()
+}
 
 #[derive(Debug, Clone, Copy)]
 enum Move {
@@ -121,100 +194,30 @@ fn parse_input(input: &[u8]) -> Vec<Move> {
         .collect()
 }
 
-fn mark_occupied(ph: usize, px: usize, s: &Shape, chamber: &mut [Row]) {
-    match s {
-        Shape::Hbar => {
-            chamber[ph][px] = true;
-            chamber[ph][px + 1] = true;
-            chamber[ph][px + 2] = true;
-            chamber[ph][px + 3] = true;
-        }
-        Shape::Vbar => {
-            chamber[ph][px] = true;
-            chamber[ph + 1][px] = true;
-            chamber[ph + 2][px] = true;
-            chamber[ph + 3][px] = true;
-        }
-        Shape::Box => {
-            chamber[ph][px] = true;
-            chamber[ph][px + 1] = true;
-            chamber[ph + 1][px] = true;
-            chamber[ph + 1][px + 1] = true;
-        }
-        Shape::FlipL => {
-            chamber[ph][px] = true;
-            chamber[ph][px + 1] = true;
-            chamber[ph][px + 2] = true;
-            chamber[ph + 1][px + 2] = true;
-            chamber[ph + 2][px + 2] = true;
-        }
-        Shape::Cross => {
-            chamber[ph][px + 1] = true;
-            chamber[ph + 1][px] = true;
-            chamber[ph + 1][px + 1] = true;
-            chamber[ph + 1][px + 2] = true;
-            chamber[ph + 2][px + 1] = true;
-        }
-    }
-}
-
-#[allow(dead_code)]
-fn draw_chamber(chamber: &[Row], s: &Shape, ph: usize, px: usize, max_height: usize) {
-    println!();
-    println!();
-    println!("{:?}: {} {}", s, ph, px);
-    for h in (0..max_height).rev() {
-        println!(
-            "{}",
-            chamber[h]
-                .iter()
-                .map(|x| match x {
-                    true => '#',
-                    false => '.',
-                })
-                .collect::<String>()
-        );
-    }
-}
-
 #[allow(dead_code)]
 fn count_occupied_rows(chamber: &[Row]) -> usize {
-    chamber
-        .iter()
-        .filter(|r| r.iter().map(|e| *e as u8).sum::<u8>() > 0)
-        .count()
+    chamber.iter().filter(|r| **r != 0).count()
 }
 
-fn drop_block(
-    chamber: &mut [Row],
-    blocks: &mut impl Iterator<Item = (usize, Shape)>,
+fn drop_block<'a>(
+    chamber: &mut Vec<Row>,
+    width: usize,
+    blocks: &mut impl Iterator<Item = (usize, &'a Rock)>,
     moves: &mut impl Iterator<Item = (usize, Move)>,
     maxh: usize,
 ) -> (usize, usize, usize) {
     let mut ph = maxh + 3;
-    let mut px = 2;
+    let mut px: usize = 2;
 
     let (si, s) = blocks.next().unwrap();
 
+    if chamber.len() < ph + s.height() {
+        chamber.resize(ph + s.height(), 0);
+    }
+
     let mi = loop {
         let (mi, m) = moves.next().unwrap();
-        match m {
-            Move::Left => {
-                if !s.side_collision(&m, ph, px, chamber) {
-                    px -= 1;
-                }
-            }
-            Move::Right => {
-                if !s.side_collision(&m, ph, px, chamber) {
-                    px += 1;
-                }
-            }
-        }
-
-        if !s.bottom_collision(ph, px, chamber) {
-            ph -= 1;
-        } else {
-            mark_occupied(ph, px, &s, chamber);
+        if push_and_fall(s, chamber, width, &mut ph, &mut px, m) {
             break mi;
         }
     };
@@ -222,108 +225,490 @@ fn drop_block(
     (si, mi, std::cmp::max(maxh, ph + s.height()))
 }
 
-fn simulate(
-    target_rocks: usize,
-    mut blocks: &mut impl Iterator<Item = (usize, Shape)>,
-    mut moves: &mut impl Iterator<Item = (usize, Move)>,
-) -> usize {
-    let mut chamber = vec![Row::default(); MAX_H];
+// Pushes the rock with one jet and then lets it fall one unit. Returns true if it came to rest,
+// in which case it's been settled into the chamber.
+fn push_and_fall(
+    s: &Rock,
+    chamber: &mut [Row],
+    width: usize,
+    ph: &mut usize,
+    px: &mut usize,
+    m: Move,
+) -> bool {
+    let nx = match m {
+        Move::Left => px.checked_sub(1),
+        Move::Right => (*px + s.width < width).then_some(*px + 1),
+    };
+
+    if let Some(nx) = nx {
+        if !s.collides(chamber, *ph, nx) {
+            *px = nx;
+        }
+    }
+
+    if *ph > 0 && !s.collides(chamber, *ph - 1, *px) {
+        *ph -= 1;
+        false
+    } else {
+        s.settle(chamber, *ph, *px);
+        true
+    }
+}
+
+fn stack_rocks(target_rocks: usize, config: &Config, moves: &[Move]) -> (Vec<Row>, usize) {
+    let mut blocks = config.rocks.iter().enumerate().cycle();
+    let mut moves = moves.iter().copied().enumerate().cycle();
+
+    let mut chamber = vec![];
     let mut maxh = 0;
 
     for _ in 0..target_rocks {
-        let (_, _, height) = drop_block(&mut chamber, &mut blocks, &mut moves, maxh);
+        let (_, _, height) = drop_block(&mut chamber, config.width, &mut blocks, &mut moves, maxh);
         maxh = height;
     }
 
-    maxh
+    (chamber, maxh)
 }
 
-#[derive(Debug, Hash, Eq, PartialEq)]
+fn simulate(target_rocks: usize, config: &Config, moves: &[Move]) -> usize {
+    stack_rocks(target_rocks, config, moves).1
+}
+
+fn draw_chamber(
+    chamber: &[Row],
+    width: usize,
+    maxh: usize,
+    falling: Option<(&Rock, usize, usize)>,
+    nrows: usize,
+) -> String {
+    let top = match falling {
+        Some((s, ph, _)) => std::cmp::max(maxh, ph + s.height()),
+        None => maxh,
+    };
+
+    let mut out = String::new();
+    for h in (top.saturating_sub(nrows)..top).rev() {
+        let rock_row = match falling {
+            Some((s, ph, px)) if h >= ph && h < ph + s.height() => s.rows[h - ph] >> px,
+            _ => 0,
+        };
+        let row = chamber.get(h).copied().unwrap_or(0);
+
+        out.push('|');
+        for x in 0..width {
+            out.push(
+                match (rock_row & column_bit(x) != 0, row & column_bit(x) != 0) {
+                    (true, _) => '@',
+                    (false, true) => '#',
+                    (false, false) => '.',
+                },
+            );
+        }
+        out.push_str("|\n");
+    }
+
+    if nrows >= top {
+        out.push_str(&format!("+{}+\n", "-".repeat(width)));
+    }
+
+    out
+}
+
+// Draws the top `nrows` rows of the tower after `nrocks` have settled, with the next rock shown
+// where it appears
+fn render(config: &Config, moves: &[Move], nrocks: usize, nrows: usize) -> String {
+    let (chamber, maxh) = stack_rocks(nrocks, config, moves);
+    let next = &config.rocks[nrocks % config.rocks.len()];
+
+    draw_chamber(
+        &chamber,
+        config.width,
+        maxh,
+        Some((next, maxh + 3, 2)),
+        nrows,
+    )
+}
+
+// Like `render`, but stops after `njets` jets so the falling rock can be caught part way down
+fn render_after_jets(config: &Config, moves: &[Move], njets: usize, nrows: usize) -> String {
+    let mut rocks = config.rocks.iter().cycle();
+    let mut chamber = vec![];
+    let mut maxh = 0;
+
+    let mut s = rocks.next().unwrap();
+    let (mut ph, mut px) = (3, 2);
+
+    for &m in moves.iter().cycle().take(njets) {
+        if chamber.len() < ph + s.height() {
+            chamber.resize(ph + s.height(), 0);
+        }
+
+        if push_and_fall(s, &mut chamber, config.width, &mut ph, &mut px, m) {
+            maxh = std::cmp::max(maxh, ph + s.height());
+            s = rocks.next().unwrap();
+            (ph, px) = (maxh + 3, 2);
+        }
+    }
+
+    draw_chamber(&chamber, config.width, maxh, Some((s, ph, px)), nrows)
+}
+
+pub fn render_tower(config: &Config, nrocks: usize, nrows: usize) -> String {
+    render(
+        config,
+        &parse_input(include_bytes!("../input")),
+        nrocks,
+        nrows,
+    )
+}
+
+pub fn render_tower_after_jets(config: &Config, njets: usize, nrows: usize) -> String {
+    render_after_jets(
+        config,
+        &parse_input(include_bytes!("../input")),
+        njets,
+        nrows,
+    )
+}
+
+pub fn write_tower_ppm(config: &Config, nrocks: usize, path: &str) -> Result<()> {
+    let ppm = tower_ppm(config, &parse_input(include_bytes!("../input")), nrocks);
+    std::fs::write(path, ppm)?;
+
+    Ok(())
+}
+
+// The whole tower as a binary PPM, with each cell drawn as a small square
+fn tower_ppm(config: &Config, moves: &[Move], nrocks: usize) -> Vec<u8> {
+    const CELL: usize = 4;
+
+    let (chamber, maxh) = stack_rocks(nrocks, config, moves);
+    let (w, h) = (config.width * CELL, std::cmp::max(maxh, 1) * CELL);
+
+    let mut out = format!("P6\n{} {}\n255\n", w, h).into_bytes();
+    for y in 0..h {
+        let row = chamber
+            .get(maxh.saturating_sub(1 + y / CELL))
+            .copied()
+            .unwrap_or(0);
+        for x in 0..w {
+            match row & column_bit(x / CELL) != 0 {
+                true => out.extend_from_slice(&[90, 90, 90]),
+                false => out.extend_from_slice(&[15, 15, 35]),
+            }
+        }
+    }
+
+    out
+}
+
+// Used for part B, whichever rocks are loaded, since it can't mistake a deep open shaft for a
+// repeat of the chamber
+pub const DEFAULT_FINGERPRINT: Fingerprint = Fingerprint::Reachable;
+
+// Ceiling and TopRows only describe part of the chamber, so two states can match while an open
+// shaft deeper down differs. Verifying extra periods compares the same partial descriptions and
+// can't catch that, so for these the cycle is a heuristic. Reachable is exact.
+#[derive(Debug, Clone, Copy)]
+pub enum Fingerprint {
+    // Depth of the first occupied cell in each column
+    Ceiling,
+    // The top n rows as they are
+    TopRows(usize),
+    // Every cell a falling rock could still reach, see `get_chamber_reachable`
+    Reachable,
+}
+
+// As written on the command line: "ceiling", "top:<rows>" or "reachable"
+impl std::str::FromStr for Fingerprint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Fingerprint> {
+        match s.split_once(':') {
+            None if s == "ceiling" => Ok(Fingerprint::Ceiling),
+            None if s == "reachable" => Ok(Fingerprint::Reachable),
+            Some(("top", n)) => Ok(Fingerprint::TopRows(n.parse()?)),
+            _ => bail!("unknown fingerprint '{}'", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+enum Surface {
+    Ceiling(Vec<usize>),
+    Rows(Vec<Row>),
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
 struct State {
-    ceiling: [usize; 7],
+    surface: Surface,
     si: usize,
     mi: usize,
+    growth: usize,
 }
 
-fn get_chamber_ceiling(chamber: &[Row], maxh: usize) -> [usize; 7] {
-    let mut cc = [0; 7];
-    for (i, ccx) in cc.iter_mut().enumerate() {
-        // for i in 0..7 {
-        let mut testh = maxh;
-        let mut cnt = 0;
-        while !chamber[testh][i] {
-            if testh == 0 {
+fn get_chamber_ceiling(chamber: &[Row], width: usize, maxh: usize) -> Vec<usize> {
+    (0..width)
+        .map(|i| {
+            chamber[..maxh]
+                .iter()
+                .rev()
+                .position(|r| r & column_bit(i) != 0)
+                .unwrap_or(maxh)
+        })
+        .collect()
+}
+
+fn get_chamber_top(chamber: &[Row], maxh: usize, nrows: usize) -> Vec<Row> {
+    chamber[maxh.saturating_sub(nrows)..maxh].to_vec()
+}
+
+// Rows from the top down to the deepest cell a falling rock could still reach, with every
+// unreachable cell marked as occupied. Unlike the other fingerprints this describes the chamber
+// exactly, but it never repeats if open shafts keep deepening.
+fn get_chamber_reachable(chamber: &[Row], width: usize, maxh: usize) -> Vec<Row> {
+    let full: Row = !Row::MAX.checked_shr(width as u32).unwrap_or(0);
+    let mut rows = vec![];
+    let mut reach = full;
+
+    for h in (0..maxh).rev() {
+        let open = !chamber[h] & full;
+        reach &= open;
+        loop {
+            let spread = reach | ((reach << 1) | (reach >> 1)) & open;
+            if spread == reach {
                 break;
             }
-            testh -= 1;
-            cnt += 1;
+            reach = spread;
+        }
+
+        rows.push(!reach & full);
+        if reach == 0 {
+            break;
         }
-        *ccx = cnt;
     }
 
-    cc
+    rows
 }
 
 fn simulate_cycle(
     target_rocks: usize,
-    mut blocks: &mut impl Iterator<Item = (usize, Shape)>,
-    mut moves: &mut impl Iterator<Item = (usize, Move)>,
-) -> usize {
-    let mut chamber = vec![Row::default(); MAX_H];
-    let mut maxh = 0;
-
-    let mut cache: HashMap<State, (usize, usize)> = HashMap::new();
+    config: &Config,
+    moves: &[Move],
+    fingerprint: Fingerprint,
+    verify_periods: usize,
+) -> (usize, Option<Cycle>) {
+    let mut blocks = config.rocks.iter().enumerate().cycle();
+    let mut moves = moves.iter().copied().enumerate().cycle();
 
-    for nrocks in 1..=target_rocks {
-        let (si, mi, height) = drop_block(&mut chamber, &mut blocks, &mut moves, maxh);
+    let mut chamber = vec![];
+    let mut maxh = 0;
 
+    // heights[i] and the i-th state both describe the chamber after i + 1 rocks
+    let mut heights = vec![];
+    let states = (0..target_rocks).map(|_| {
+        let (si, mi, height) =
+            drop_block(&mut chamber, config.width, &mut blocks, &mut moves, maxh);
+        let growth = height - maxh;
         maxh = height;
+        heights.push(maxh);
 
-        let ceiling = get_chamber_ceiling(&chamber, maxh);
-        let state = State { ceiling, si, mi };
-
-        if cache.contains_key(&state) {
-            let (nrocks_prev, maxh_prev) = cache.get(&state).unwrap();
-            let nrocks_cycle = nrocks - nrocks_prev;
-
-            let delta_height = maxh - maxh_prev;
-            let repeats = (target_rocks - nrocks) / nrocks_cycle;
-            let addtl_drops = (target_rocks - nrocks) - (repeats * nrocks_cycle);
-            let mut total_height = delta_height * repeats;
-
-            for _ in 0..addtl_drops {
-                let (_, _, height) = drop_block(&mut chamber, &mut blocks, &mut moves, maxh);
-                maxh = height;
+        let surface = match fingerprint {
+            Fingerprint::Ceiling => {
+                Surface::Ceiling(get_chamber_ceiling(&chamber, config.width, maxh))
             }
-
-            total_height += maxh;
-            return total_height;
-        } else {
-            cache.insert(state, (nrocks, maxh));
+            Fingerprint::TopRows(n) => Surface::Rows(get_chamber_top(&chamber, maxh, n)),
+            Fingerprint::Reachable => {
+                Surface::Rows(get_chamber_reachable(&chamber, config.width, maxh))
+            }
+        };
+        State {
+            surface,
+            si,
+            mi,
+            growth,
         }
-    }
+    });
 
-    maxh
+    match cycle::detect_verified(states, verify_periods) {
+        Some(c) => (c.extrapolate(target_rocks - 1, &heights), Some(c)),
+        None => (maxh, None),
+    }
 }
 
 pub fn solve_a() -> Result<usize> {
-    let moves = parse_input(include_bytes!("../input"));
+    solve_a_with(&Config::default())
+}
 
-    let mut blocks = SHAPES.iter().copied().enumerate().cycle();
-    let mut moves = moves.iter().copied().enumerate().cycle();
+pub fn solve_a_with(config: &Config) -> Result<usize> {
+    let moves = parse_input(include_bytes!("../input"));
 
-    let x = simulate(2022, &mut blocks, &mut moves);
+    let x = simulate(2022, config, &moves);
 
     Ok(x)
 }
 
 pub fn solve_b() -> Result<usize> {
-    let moves = parse_input(include_bytes!("../input"));
+    let (x, _) = solve_b_with(&Config::default(), DEFAULT_FINGERPRINT, 1)?;
 
-    let mut blocks = SHAPES.iter().copied().enumerate().cycle();
-    let mut moves = moves.iter().copied().enumerate().cycle();
+    Ok(x)
+}
+
+pub fn solve_b_with(
+    config: &Config,
+    fingerprint: Fingerprint,
+    verify_periods: usize,
+) -> Result<(usize, Option<Cycle>)> {
+    let moves = parse_input(include_bytes!("../input"));
 
-    let x = simulate_cycle(1_000_000_000_000, &mut blocks, &mut moves);
+    let x = simulate_cycle(
+        1_000_000_000_000,
+        config,
+        &moves,
+        fingerprint,
+        verify_periods,
+    );
 
     Ok(x)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lcg::Lcg;
+
+    #[test]
+    fn test_parse_rocks() {
+        let rocks = parse_rocks(include_str!("../rocks")).unwrap();
+
+        assert_eq!(rocks.len(), 5);
+        assert_eq!(rocks[2].rows, vec![0b1110_0000, 0b0010_0000, 0b0010_0000]);
+        assert_eq!(rocks[2].width, 3);
+        assert_eq!(rocks[3].width, 1);
+
+        let rock = Rock::new(vec![0b0000_0110, 0b0000_0010]).unwrap();
+        assert_eq!(rock.rows, vec![0b1100_0000, 0b0100_0000]);
+        assert_eq!(rock.width, 2);
+
+        assert!(parse_rocks("#x#\n").is_err());
+        assert!(parse_rocks("#########\n").is_err());
+        assert!(Config::new(4, rocks).is_err());
+
+        // Blank lines in a rock don't make it any taller
+        let rocks = parse_rocks("...\n###\n...\n").unwrap();
+        assert_eq!(rocks[0].rows, vec![0b1110_0000]);
+        let config = Config::new(7, rocks).unwrap();
+        assert_eq!(simulate(3, &config, &parse_input(b"<")), 3);
+    }
+
+    #[test]
+    fn test_render() {
+        let config = Config::default();
+        let moves = parse_input(include_bytes!("../example1"));
+
+        assert_eq!(
+            render(&config, &moves, 0, 10),
+            "|..@@@@.|\n|.......|\n|.......|\n|.......|\n+-------+\n"
+        );
+
+        assert_eq!(
+            render(&config, &moves, 1, 10),
+            "|...@...|\n|..@@@..|\n|...@...|\n|.......|\n|.......|\n|.......|\n|..####.|\n+-------+\n"
+        );
+
+        assert_eq!(render(&config, &moves, 1, 2), "|...@...|\n|..@@@..|\n");
+    }
+
+    #[test]
+    fn test_render_after_jets() {
+        let config = Config::default();
+        let moves = parse_input(include_bytes!("../example1"));
+
+        assert_eq!(
+            render_after_jets(&config, &moves, 0, 10),
+            render(&config, &moves, 0, 10)
+        );
+
+        // Pushed right by the first jet, then down a unit
+        assert_eq!(
+            render_after_jets(&config, &moves, 1, 10),
+            "|...@@@@|\n|.......|\n|.......|\n+-------+\n"
+        );
+
+        // The first rock comes to rest on the fourth jet
+        assert_eq!(
+            render_after_jets(&config, &moves, 4, 10),
+            render(&config, &moves, 1, 10)
+        );
+    }
+
+    #[test]
+    fn test_tower_ppm() {
+        let config = Config::default();
+        let moves = parse_input(include_bytes!("../example1"));
+
+        let ppm = tower_ppm(&config, &moves, 10);
+        let header = format!("P6\n{} {}\n255\n", 7 * 4, 17 * 4);
+        assert!(ppm.starts_with(header.as_bytes()));
+        assert_eq!(ppm.len(), header.len() + 7 * 4 * 17 * 4 * 3);
+
+        // An empty chamber still makes a valid image
+        let ppm = tower_ppm(&config, &moves, 0);
+        assert_eq!(ppm.len(), "P6\n28 4\n255\n".len() + 28 * 4 * 3);
+    }
+
+    #[test]
+    fn test_fingerprints() {
+        let config = Config::default();
+        let solve = |fingerprint: &str, periods| {
+            let fingerprint = fingerprint.parse().unwrap();
+            solve_b_with(&config, fingerprint, periods).unwrap().0
+        };
+
+        // A single row repeats by coincidence long before the chamber does, and only checking
+        // another period catches it
+        assert_eq!(solve("top:1", 0), 1531594202903);
+        assert_eq!(solve("top:1", 1), 1523615160362);
+        assert_eq!(solve("top:30", 0), 1523615160362);
+        assert_eq!(solve("ceiling", 1), 1523615160362);
+        assert_eq!(solve("reachable", 0), 1523615160362);
+
+        assert!("top".parse::<Fingerprint>().is_err());
+        assert!("top:x".parse::<Fingerprint>().is_err());
+    }
+
+    #[test]
+    fn test_cycle_matches_brute_force() {
+        let mut rng = Lcg::new(17);
+        let mut rand = |n: u64| rng.below(n) as usize;
+
+        for _ in 0..30 {
+            let width = 4 + rand(5);
+            let rocks = (0..1 + rand(4))
+                .map(|_| {
+                    // Placed anywhere in the row, since `Rock::new` moves it to the left edge
+                    let rock_width = 1 + rand((width - 2) as u64);
+                    let shift = rand((Row::BITS as usize - rock_width + 1) as u64);
+                    let rows = (0..1 + rand(4))
+                        .map(|_| (1 + rand((1 << rock_width) - 1)) as Row)
+                        .map(|r| r << shift)
+                        .collect();
+                    Rock::new(rows).unwrap()
+                })
+                .collect();
+            let config = Config::new(width, rocks).unwrap();
+
+            let moves = (0..5 + rand(40))
+                .map(|_| match rand(2) {
+                    0 => Move::Left,
+                    _ => Move::Right,
+                })
+                .collect::<Vec<_>>();
+
+            let target = 1000 + rand(1000);
+            let (height, _) = simulate_cycle(target, &config, &moves, DEFAULT_FINGERPRINT, 1);
+
+            assert_eq!(height, simulate(target, &config, &moves), "{:?}", config);
+        }
+    }
+}
@@ -1,9 +1,40 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use rayon::prelude::*;
 
 pub fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if !args.is_empty() {
+        let config = parse_args(&args)?;
+        if let Some(id) = config.explain {
+            print!("{}", explain(id, config.minutes)?);
+            return Ok(());
+        }
+
+        let reports = run(&config, |r| {
+            eprintln!(
+                "Blueprint {}: {} geodes, quality {} in {:?}{}",
+                r.id,
+                r.geodes,
+                r.quality,
+                r.elapsed,
+                if r.timed_out { " (time limit hit)" } else { "" }
+            )
+        })?;
+
+        println!(
+            "total quality: {}",
+            reports.iter().map(|r| r.quality).sum::<u32>()
+        );
+        println!(
+            "geode product: {}",
+            reports.iter().map(|r| r.geodes).product::<u32>()
+        );
+        return Ok(());
+    }
+
     let start = std::time::Instant::now();
     let soln_a = solve_a()?;
     eprintln!("Part A elapsed {:?}", start.elapsed());
@@ -17,273 +48,676 @@ pub fn main() -> Result<()> {
     Ok(())
 }
 
-#[derive(Debug)]
-enum Material {
-    Geode,
-    Obsidian,
-    Clay,
-    Ore,
-}
-
-const MATERIALS: [Material; 4] = [
-    Material::Geode,
-    Material::Obsidian,
-    Material::Clay,
-    Material::Ore,
-];
+const GOAL: &str = "geode";
 
 #[derive(Debug, Eq, PartialEq)]
-struct RobotCost(u32, u32, u32);
+struct Recipe {
+    material: usize,
+    cost: Vec<u32>,
+}
 
 #[derive(Debug)]
 struct Blueprint {
-    cost: [RobotCost; 4],
-    max_cost_ore: u32,
-    max_cost_clay: u32,
-    max_cost_obsidian: u32,
+    id: u32,
+    materials: Vec<String>,
+    recipes: Vec<Recipe>,
+    goal: usize,
+    max_cost: Vec<u32>,
 }
 
-impl Blueprint {
-    fn from_str(s: &str) -> Blueprint {
-        let x = s
-            .split_ascii_whitespace()
-            .skip(1)
-            .filter_map(|w| w.parse::<u32>().ok())
-            .collect::<Vec<_>>();
+impl FromStr for Blueprint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Blueprint> {
+        let (header, body) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("missing ':' in blueprint '{}'", s.trim()))?;
+        let id = header
+            .trim()
+            .strip_prefix("Blueprint")
+            .and_then(|x| x.trim().parse().ok())
+            .ok_or_else(|| anyhow!("bad blueprint header '{}'", header.trim()))?;
+
+        let mut materials: Vec<String> = vec![];
+        let mut intern = |name: &str| match materials.iter().position(|m| m == name) {
+            Some(i) => i,
+            None => {
+                materials.push(name.to_string());
+                materials.len() - 1
+            }
+        };
+
+        let mut recipes = vec![];
+        for sentence in body.split('.').map(str::trim).filter(|x| !x.is_empty()) {
+            let words = sentence.split_ascii_whitespace().collect::<Vec<_>>();
+            let (robot, costs) = match words.as_slice() {
+                ["Each", robot, "robot", "costs", costs @ ..] if !costs.is_empty() => {
+                    (robot, costs)
+                }
+                _ => bail!("unrecognised sentence '{}'", sentence),
+            };
+
+            let material = intern(robot);
+            let cost = costs
+                .iter()
+                .map(|w| w.trim_end_matches(','))
+                .filter(|w| *w != "and")
+                .collect::<Vec<_>>()
+                .chunks(2)
+                .map(|c| match c {
+                    [n, m] => Ok((n.parse::<u32>()?, intern(m))),
+                    _ => bail!("unrecognised cost in '{}'", sentence),
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            if recipes.iter().any(|r: &(usize, _)| r.0 == material) {
+                bail!("{} robot is defined twice", robot);
+            }
+            recipes.push((material, cost));
+        }
 
-        let cost = [
-            RobotCost(x[0], 0, 0),
-            RobotCost(x[1], 0, 0),
-            RobotCost(x[2], x[3], 0),
-            RobotCost(x[4], 0, x[5]),
-        ];
-
-        let max_cost_ore = cost.iter().map(|c| c.0).max().unwrap();
-        let max_cost_clay = cost.iter().map(|c| c.1).max().unwrap();
-        let max_cost_obsidian = cost.iter().map(|c| c.2).max().unwrap();
-
-        Blueprint {
-            cost,
-            max_cost_ore,
-            max_cost_clay,
-            max_cost_obsidian,
+        let goal = materials
+            .iter()
+            .position(|m| m == GOAL)
+            .ok_or_else(|| anyhow!("blueprint {} never mentions {}", id, GOAL))?;
+
+        if recipes.is_empty() {
+            bail!("blueprint {} has no robots", id);
         }
+
+        let n = materials.len();
+        let recipes = recipes
+            .into_iter()
+            .map(|(material, c)| {
+                let mut cost = vec![0; n];
+                for (amount, m) in c {
+                    cost[m] += amount;
+                }
+                Recipe { material, cost }
+            })
+            .collect::<Vec<_>>();
+
+        let max_cost = (0..n)
+            .map(|m| recipes.iter().map(|r| r.cost[m]).max().unwrap())
+            .collect();
+
+        Ok(Blueprint {
+            id,
+            materials,
+            recipes,
+            goal,
+            max_cost,
+        })
     }
+}
 
-    fn build_cost(&self, robot: &Material) -> &RobotCost {
-        match robot {
-            Material::Ore => &self.cost[0],
-            Material::Clay => &self.cost[1],
-            Material::Obsidian => &self.cost[2],
-            Material::Geode => &self.cost[3],
+impl Blueprint {
+    fn build_cost(&self, robot: usize) -> &[u32] {
+        &self.recipes[robot].cost
+    }
+
+    fn robot_name(&self, m: usize) -> String {
+        match m == self.goal {
+            true => format!("{}-cracking robot", self.materials[m]),
+            false => format!("{}-collecting robot", self.materials[m]),
         }
     }
 
-    fn can_build(&self, robot: &Material, state: &State) -> bool {
-        let c = self.build_cost(robot);
-        state.n_ores >= c.0 && state.n_clay >= c.1 && state.n_obsidian >= c.2
+    // Only one robot can be built per minute, so there is never a reason to produce more of a
+    // material per minute than the most expensive recipe consumes
+    fn worth_building(&self, robot: usize, state: &State) -> bool {
+        let m = self.recipes[robot].material;
+        m == self.goal || state.robots[m] < self.max_cost[m]
+    }
+
+    fn time_to_afford(&self, robot: usize, state: &State) -> Option<u32> {
+        self.build_cost(robot)
+            .iter()
+            .zip(state.amounts.iter().zip(state.robots.iter()))
+            .try_fold(0, |wait, (&cost, (&have, &rate))| {
+                match (have >= cost, rate) {
+                    (true, _) => Some(wait),
+                    (false, 0) => None,
+                    (false, _) => Some(std::cmp::max(wait, (cost - have).div_ceil(rate))),
+                }
+            })
     }
 
-    fn heuristic(&self, robot: &Material, state: &State) -> bool {
-        let h1 = match robot {
-            Material::Ore => state.ore_robots <= self.max_cost_ore,
-            Material::Clay => state.clay_robots <= self.max_cost_clay,
-            Material::Obsidian => state.obsidian_robots <= self.max_cost_obsidian,
-            _ => unreachable!(),
+    // Optimistic goal count: pretend one extra robot of every other material appears each minute
+    // for free, and a goal robot is built whenever its cost is covered
+    fn upper_bound(&self, state: &State, remaining: u32) -> u32 {
+        let Some(goal_robot) = self.recipes.iter().find(|r| r.material == self.goal) else {
+            return state.amounts[self.goal] + state.robots[self.goal] * remaining;
         };
 
-        h1
+        let mut amounts = state.amounts.clone();
+        let mut robots = state.robots.clone();
+
+        for _ in 0..remaining {
+            let build = amounts.iter().zip(&goal_robot.cost).all(|(a, c)| a >= c);
+            if build {
+                amounts
+                    .iter_mut()
+                    .zip(&goal_robot.cost)
+                    .for_each(|(a, c)| *a -= c);
+            }
+
+            amounts.iter_mut().zip(&robots).for_each(|(a, r)| *a += r);
+
+            for (m, r) in robots.iter_mut().enumerate() {
+                if m != self.goal {
+                    *r += 1;
+                }
+            }
+            if build {
+                robots[self.goal] += 1;
+            }
+        }
+
+        amounts[self.goal]
     }
 }
 
-#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 struct State {
-    ore_robots: u32,
-    clay_robots: u32,
-    obsidian_robots: u32,
-    geode_robots: u32,
-    n_ores: u32,
-    n_clay: u32,
-    n_obsidian: u32,
-    n_geodes: u32,
+    robots: Vec<u32>,
+    amounts: Vec<u32>,
 }
 
 impl State {
-    fn new() -> State {
+    // The first robot described by a blueprint is the one you start out with
+    fn new(bp: &Blueprint) -> State {
+        let mut robots = vec![0; bp.materials.len()];
+        robots[bp.recipes[0].material] = 1;
+
         State {
-            ore_robots: 1,
-            ..Default::default()
+            robots,
+            amounts: vec![0; bp.materials.len()],
         }
     }
 
     fn collect_material(&mut self) {
-        self.n_ores += self.ore_robots;
-        self.n_clay += self.clay_robots;
-        self.n_obsidian += self.obsidian_robots;
-        self.n_geodes += self.geode_robots;
+        self.amounts
+            .iter_mut()
+            .zip(&self.robots)
+            .for_each(|(a, r)| *a += r);
     }
 
-    fn build(&mut self, robot: &Material, bp: &Blueprint) {
+    fn build(&mut self, robot: usize, bp: &Blueprint) {
         self.collect_material();
 
-        let c = bp.build_cost(robot);
-        self.n_ores -= c.0;
-        self.n_clay -= c.1;
-        self.n_obsidian -= c.2;
+        self.amounts
+            .iter_mut()
+            .zip(bp.build_cost(robot))
+            .for_each(|(a, c)| *a -= c);
 
-        match robot {
-            Material::Ore => self.ore_robots += 1,
-            Material::Clay => self.clay_robots += 1,
-            Material::Obsidian => self.obsidian_robots += 1,
-            Material::Geode => self.geode_robots += 1,
-        }
+        self.robots[bp.recipes[robot].material] += 1;
     }
+}
 
-    fn stabilize(&mut self, bp: &Blueprint) {
-        if self.n_ores > bp.max_cost_ore && self.ore_robots > bp.max_cost_ore {
-            self.n_ores = bp.max_cost_ore;
-        }
+fn parse_input(input: &str) -> Result<Vec<Blueprint>> {
+    input
+        .split("Blueprint")
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| format!("Blueprint{}", x).parse())
+        .collect()
+}
 
-        if self.n_clay > bp.max_cost_clay && self.clay_robots > bp.max_cost_clay {
-            self.n_clay = bp.max_cost_clay;
+#[derive(Debug, Clone)]
+struct Build {
+    minute: u32,
+    robot: usize,
+    state: State,
+}
+
+#[derive(Debug, Default)]
+struct Best {
+    geodes: u32,
+    builds: Vec<Build>,
+    timed_out: bool,
+}
+
+fn search(
+    bp: &Blueprint,
+    state: &State,
+    remaining: u32,
+    deadline: Option<Instant>,
+    path: &mut Vec<Build>,
+    best: &mut Best,
+) {
+    let geodes = state.amounts[bp.goal] + state.robots[bp.goal] * remaining;
+    if geodes > best.geodes {
+        best.geodes = geodes;
+        best.builds = path.clone();
+    }
+
+    if best.timed_out || bp.upper_bound(state, remaining) <= best.geodes {
+        return;
+    }
+
+    if deadline.is_some_and(|d| Instant::now() > d) {
+        best.timed_out = true;
+        return;
+    }
+
+    for robot in (0..bp.recipes.len()).rev() {
+        if !bp.worth_building(robot, state) {
+            continue;
         }
 
-        if self.n_obsidian > bp.max_cost_obsidian && self.obsidian_robots > bp.max_cost_obsidian {
-            self.n_obsidian = bp.max_cost_obsidian;
+        // A robot finished in the last minute can't collect anything
+        let wait = match bp.time_to_afford(robot, state) {
+            Some(wait) if wait + 1 < remaining => wait,
+            _ => continue,
+        };
+
+        let mut new_state = state.clone();
+        for _ in 0..wait {
+            new_state.collect_material();
         }
+        new_state.build(robot, bp);
+
+        let minute = path.last().map_or(0, |b| b.minute) + wait + 1;
+        path.push(Build {
+            minute,
+            robot,
+            state: new_state.clone(),
+        });
+        search(bp, &new_state, remaining - wait - 1, deadline, path, best);
+        path.pop();
     }
 }
 
-fn parse_input(input: &str) -> Vec<Blueprint> {
-    input
-        .lines()
-        .map(|line| Blueprint::from_str(line))
-        .collect()
-}
+fn find_max_geodes(bp: &Blueprint, time: u32, deadline: Option<Instant>) -> Best {
+    let mut best = Best::default();
+    search(bp, &State::new(bp), time, deadline, &mut vec![], &mut best);
 
-fn find_max_geodes(bp: &Blueprint, time: u32) -> u32 {
-    let mut queue = VecDeque::new();
-    queue.push_back((State::new(), 0));
+    best
+}
 
-    let mut cache: HashMap<u32, u32> = (0..=time).map(|x| (x as u32, 0)).collect();
-    let mut seen = HashSet::new();
+fn narrate(bp: &Blueprint, time: u32, builds: &[Build]) -> String {
+    let mut state = State::new(bp);
+    let mut out = vec![];
+
+    for minute in 1..=time {
+        let mut lines = vec![format!("== Minute {} ==", minute)];
+        let prev = state.clone();
+        let build = builds.iter().find(|b| b.minute == minute);
+
+        match build {
+            Some(b) => {
+                let spend = bp
+                    .build_cost(b.robot)
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, n)| **n > 0)
+                    .map(|(m, n)| format!("{} {}", n, bp.materials[m]))
+                    .collect::<Vec<_>>()
+                    .join(" and ");
+                let name = bp.robot_name(bp.recipes[b.robot].material);
+                let article = match name.starts_with(['a', 'e', 'i', 'o', 'u']) {
+                    true => "an",
+                    false => "a",
+                };
+                lines.push(format!(
+                    "Spend {} to start building {} {}.",
+                    spend, article, name
+                ));
+                state = b.state.clone();
+            }
+            None => state.collect_material(),
+        }
 
-    while let Some((state, t)) = queue.pop_front() {
-        let &prev_max = cache.get(&t).unwrap();
+        for r in bp.recipes.iter() {
+            let m = r.material;
+            let n = prev.robots[m];
+            if n == 0 {
+                continue;
+            }
 
-        if state.n_geodes + 2 < prev_max {
-            continue;
+            let total = state.amounts[m];
+            let plural = |x: u32| if x == 1 { "" } else { "s" };
+            let verb_s = if n == 1 { "s" } else { "" };
+            let name = &bp.materials[m];
+            lines.push(match m == bp.goal {
+                true => format!(
+                    "{} {}{} crack{} {} {}{}; you now have {} open {}{}.",
+                    n,
+                    bp.robot_name(m),
+                    plural(n),
+                    verb_s,
+                    n,
+                    name,
+                    plural(n),
+                    total,
+                    name,
+                    plural(total)
+                ),
+                false => format!(
+                    "{} {}{} collect{} {} {}; you now have {} {}.",
+                    n,
+                    bp.robot_name(m),
+                    plural(n),
+                    verb_s,
+                    n,
+                    name,
+                    total,
+                    name
+                ),
+            });
         }
 
-        if seen.contains(&state) {
-            continue;
+        if let Some(b) = build {
+            let m = bp.recipes[b.robot].material;
+            lines.push(format!(
+                "The new {} is ready; you now have {} of them.",
+                bp.robot_name(m),
+                state.robots[m]
+            ));
         }
 
-        seen.insert(state);
+        out.push(lines.join("\n"));
+    }
 
-        cache.insert(t, std::cmp::max(prev_max, state.n_geodes));
+    out.join("\n\n") + "\n"
+}
 
-        if t == time {
-            continue;
-        }
+pub fn explain(blueprint_id: u32, time: u32) -> Result<String> {
+    let blueprints = parse_input(include_str!("../input"))?;
+    let bp = blueprints
+        .iter()
+        .find(|bp| bp.id == blueprint_id)
+        .ok_or_else(|| anyhow!("no blueprint with id {}", blueprint_id))?;
 
-        if bp.can_build(&Material::Geode, &state) {
-            let mut new_state = state;
-            new_state.build(&Material::Geode, bp);
-            new_state.stabilize(bp);
+    let best = find_max_geodes(bp, time, None);
 
-            queue.push_back((new_state, t + 1));
-            continue;
+    Ok(narrate(bp, time, &best.builds))
+}
+
+#[derive(Debug, Clone)]
+pub struct RunConfig {
+    pub minutes: u32,
+    pub blueprints: Option<Vec<u32>>,
+    pub threads: Option<usize>,
+    pub time_limit: Option<Duration>,
+    // Narrate the best plan for this blueprint instead of running them all
+    pub explain: Option<u32>,
+}
+
+impl Default for RunConfig {
+    fn default() -> RunConfig {
+        RunConfig {
+            minutes: 24,
+            blueprints: None,
+            threads: None,
+            time_limit: None,
+            explain: None,
         }
+    }
+}
 
-        let mut new_state = state;
-        new_state.collect_material();
-        queue.push_back((new_state, t + 1));
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub id: u32,
+    pub geodes: u32,
+    pub quality: u32,
+    pub elapsed: Duration,
+    pub timed_out: bool,
+}
 
-        for robot in MATERIALS.iter().skip(1) {
-            if bp.can_build(robot, &state) && bp.heuristic(robot, &state) {
-                let mut new_state = state;
-                new_state.build(robot, bp);
-                new_state.stabilize(bp);
+// Evaluates the selected blueprints, calling `progress` as each one finishes. A blueprint that hits
+// the time limit reports the best schedule found so far, which may not be optimal.
+pub fn run(config: &RunConfig, progress: impl Fn(&Report) + Sync) -> Result<Vec<Report>> {
+    let blueprints = parse_input(include_str!("../input"))?;
+
+    let selected = match &config.blueprints {
+        None => blueprints.iter().collect::<Vec<_>>(),
+        Some(ids) => ids
+            .iter()
+            .map(|id| {
+                blueprints
+                    .iter()
+                    .find(|bp| bp.id == *id)
+                    .ok_or_else(|| anyhow!("no blueprint with id {}", id))
+            })
+            .collect::<Result<Vec<_>>>()?,
+    };
+
+    let evaluate = || {
+        selected
+            .par_iter()
+            .map(|bp| {
+                let start = Instant::now();
+                let best =
+                    find_max_geodes(bp, config.minutes, config.time_limit.map(|t| start + t));
+                let report = Report {
+                    id: bp.id,
+                    geodes: best.geodes,
+                    quality: best.geodes * bp.id,
+                    elapsed: start.elapsed(),
+                    timed_out: best.timed_out,
+                };
+                progress(&report);
+                report
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let reports = match config.threads {
+        Some(n) => rayon::ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build()?
+            .install(evaluate),
+        None => evaluate(),
+    };
+
+    Ok(reports)
+}
 
-                queue.push_back((new_state, t + 1));
+fn parse_args(args: &[String]) -> Result<RunConfig> {
+    let mut config = RunConfig::default();
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| anyhow!("missing value for {}", flag))?;
+
+        match flag.as_str() {
+            "--minutes" => config.minutes = value.parse()?,
+            "--threads" => config.threads = Some(value.parse()?),
+            "--time-limit-ms" => config.time_limit = Some(Duration::from_millis(value.parse()?)),
+            "--explain" => config.explain = Some(value.parse()?),
+            "--blueprints" => {
+                config.blueprints = Some(
+                    value
+                        .split(',')
+                        .map(|x| x.parse())
+                        .collect::<std::result::Result<_, _>>()?,
+                )
             }
+            _ => bail!("unknown flag {}", flag),
         }
     }
 
-    *cache.get(&time).unwrap()
+    Ok(config)
 }
 
 pub fn solve_a() -> Result<u32> {
-    let blueprints = parse_input(include_str!("../input"));
-
-    let x = blueprints
-        .par_iter()
-        .enumerate()
-        .map(|(i, bp)| {
-            let max_geodes = find_max_geodes(bp, 24);
-            max_geodes * (i as u32 + 1)
-        })
-        .sum();
+    let reports = run(&RunConfig::default(), |_| ())?;
 
-    Ok(x)
+    Ok(reports.iter().map(|r| r.quality).sum())
 }
 
 pub fn solve_b() -> Result<u32> {
-    let blueprints = parse_input(include_str!("../input"));
-
-    let x = blueprints
-        .par_iter()
-        .take(3)
-        .map(|bp| {
-            let max_geodes = find_max_geodes(bp, 32);
-            max_geodes
-        })
-        .product();
-
-    Ok(x)
+    let config = RunConfig {
+        minutes: 32,
+        blueprints: Some(vec![1, 2, 3]),
+        ..Default::default()
+    };
+    let reports = run(&config, |_| ())?;
+
+    Ok(reports.iter().map(|r| r.geodes).product())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn robot(bp: &Blueprint, name: &str) -> usize {
+        bp.recipes
+            .iter()
+            .position(|r| bp.materials[r.material] == name)
+            .unwrap()
+    }
+
     #[test]
     fn test_cost() {
-        let blueprints = parse_input("Blueprint 1: Each ore robot costs 3 ore. Each clay robot costs 4 ore. Each obsidian robot costs 4 ore and 18 clay. Each geode robot costs 3 ore and 8 obsidian.\n");
+        let blueprints = parse_input("Blueprint 1: Each ore robot costs 3 ore. Each clay robot costs 4 ore. Each obsidian robot costs 4 ore and 18 clay. Each geode robot costs 3 ore and 8 obsidian.\n").unwrap();
 
         assert_eq!(blueprints.len(), 1);
         let bp = &blueprints[0];
+        assert_eq!(bp.materials, vec!["ore", "clay", "obsidian", "geode"]);
 
-        let c = bp.build_cost(&Material::Geode);
-        assert_eq!(*c, RobotCost(3, 0, 8));
+        let c = bp.build_cost(robot(bp, "geode"));
+        assert_eq!(c, [3, 0, 8, 0]);
 
-        let c = bp.build_cost(&Material::Ore);
-        assert_eq!(*c, RobotCost(3, 0, 0));
+        let c = bp.build_cost(robot(bp, "ore"));
+        assert_eq!(c, [3, 0, 0, 0]);
 
-        let c = bp.build_cost(&Material::Clay);
-        assert_eq!(*c, RobotCost(4, 0, 0));
+        let c = bp.build_cost(robot(bp, "clay"));
+        assert_eq!(c, [4, 0, 0, 0]);
 
-        let c = bp.build_cost(&Material::Obsidian);
-        assert_eq!(*c, RobotCost(4, 18, 0));
+        let c = bp.build_cost(robot(bp, "obsidian"));
+        assert_eq!(c, [4, 18, 0, 0]);
+    }
+
+    #[test]
+    fn test_parse_grammar() {
+        let input = "Blueprint 7:\n  Each sand robot costs 2 sand.\n  Each glass robot costs 1 sand, 3 heat and 2 sand.\n  Each heat robot costs 5 sand.\n  Each geode robot costs 4 glass.\n\nBlueprint 8: Each ore robot costs 1 ore. Each geode robot costs 1 obsidian.\n";
+        let blueprints = parse_input(input).unwrap();
+
+        assert_eq!(blueprints.len(), 2);
+        let bp = &blueprints[0];
+        assert_eq!(bp.id, 7);
+        assert_eq!(bp.materials, vec!["sand", "glass", "heat", "geode"]);
+        assert_eq!(
+            bp.recipes[1],
+            Recipe {
+                material: 1,
+                cost: vec![3, 0, 3, 0]
+            }
+        );
+
+        // Nothing ever produces obsidian
+        assert_eq!(find_max_geodes(&blueprints[1], 24, None).geodes, 0);
+
+        assert!(parse_input("Blueprint 1: Each ore robot costs 3 ore.\n").is_err());
+        assert!(parse_input("Blueprint 1: Each geode robot costs lots of ore.\n").is_err());
+        assert!(parse_input("Blueprint 1: Each geode robot is free.\n").is_err());
     }
 
     #[test]
     fn test_build() {
-        let blueprints = parse_input("Blueprint 1: Each ore robot costs 3 ore. Each clay robot costs 4 ore. Each obsidian robot costs 4 ore and 18 clay. Each geode robot costs 3 ore and 8 obsidian.\n");
+        let blueprints = parse_input("Blueprint 1: Each ore robot costs 3 ore. Each clay robot costs 4 ore. Each obsidian robot costs 4 ore and 18 clay. Each geode robot costs 3 ore and 8 obsidian.\n").unwrap();
 
         let bp = &blueprints[0];
+        let (ore, clay, obsidian, geode) = (0, 1, 2, 3);
+        assert_eq!(robot(bp, "geode"), geode);
+
+        let mut state = State::new(bp);
+        state.robots[obsidian] = 1;
+        state.robots[clay] = 1;
+        state.amounts[ore] = 100;
+        state.amounts[obsidian] = 100;
+        state.build(geode, bp);
+
+        assert_eq!(state.robots[geode], 1);
+        assert_eq!(state.amounts[clay], 1);
+        assert_eq!(state.amounts[ore], 98);
+        assert_eq!(state.amounts[obsidian], 93);
+    }
+
+    fn exhaustive_max_geodes(
+        bp: &Blueprint,
+        state: State,
+        remaining: u32,
+        cache: &mut std::collections::HashMap<(State, u32), u32>,
+    ) -> u32 {
+        if remaining == 0 {
+            return state.amounts[bp.goal];
+        }
+
+        if let Some(&x) = cache.get(&(state.clone(), remaining)) {
+            return x;
+        }
 
-        let mut state = State::new();
-        state.obsidian_robots = 1;
-        state.clay_robots = 1;
-        state.n_ores = 100;
-        state.n_obsidian = 100;
-        state.build(&Material::Geode, bp);
-
-        assert_eq!(state.geode_robots, 1);
-        assert_eq!(state.n_clay, 1);
-        assert_eq!(state.n_ores, 98);
-        assert_eq!(state.n_obsidian, 93);
+        let mut new_state = state.clone();
+        new_state.collect_material();
+        let mut x = exhaustive_max_geodes(bp, new_state, remaining - 1, cache);
+
+        for robot in 0..bp.recipes.len() {
+            if bp.time_to_afford(robot, &state) == Some(0) {
+                let mut new_state = state.clone();
+                new_state.build(robot, bp);
+                x = std::cmp::max(
+                    x,
+                    exhaustive_max_geodes(bp, new_state, remaining - 1, cache),
+                );
+            }
+        }
+
+        cache.insert((state, remaining), x);
+        x
+    }
+
+    #[test]
+    fn test_max_geodes() {
+        let blueprints = parse_input(include_str!("../example1")).unwrap();
+
+        assert_eq!(find_max_geodes(&blueprints[0], 24, None).geodes, 9);
+        assert_eq!(find_max_geodes(&blueprints[1], 24, None).geodes, 12);
+
+        for bp in blueprints.iter() {
+            let mut cache = std::collections::HashMap::new();
+            for time in 0..=18 {
+                let expected = exhaustive_max_geodes(bp, State::new(bp), time, &mut cache);
+                assert_eq!(find_max_geodes(bp, time, None).geodes, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_narrate() {
+        let blueprints = parse_input(include_str!("../example1")).unwrap();
+        let bp = &blueprints[0];
+
+        let Best { geodes, builds, .. } = find_max_geodes(bp, 24, None);
+        let last = builds.last().unwrap();
+        assert_eq!(
+            geodes,
+            last.state.amounts[bp.goal] + last.state.robots[bp.goal] * (24 - last.minute)
+        );
+
+        let story = narrate(bp, 24, &builds);
+        assert!(story.starts_with(
+            "== Minute 1 ==\n1 ore-collecting robot collects 1 ore; you now have 1 ore.\n\n"
+        ));
+        assert!(story.contains("Spend 2 ore to start building a clay-collecting robot."));
+        assert!(
+            story.contains("Spend 2 ore and 7 obsidian to start building a geode-cracking robot.")
+        );
+        assert!(story.ends_with("; you now have 9 open geodes.\n"));
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = ["--explain", "2", "--minutes", "32"].map(String::from);
+        let config = parse_args(&args).unwrap();
+        assert_eq!(config.explain, Some(2));
+        assert_eq!(config.minutes, 32);
+
+        assert!(parse_args(&["--explain".to_string()]).is_err());
+        assert!(parse_args(&["--explain", "x"].map(String::from)).is_err());
     }
 }
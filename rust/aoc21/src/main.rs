@@ -1,8 +1,22 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 
 pub fn main() -> Result<()> {
+    let args = std::env::args().collect::<Vec<_>>();
+    match &args[1..] {
+        [] => {}
+        [cmd, var] if cmd == "equation" => {
+            println!("{}", equation(include_str!("../input"), var)?);
+            return Ok(());
+        }
+        [cmd, var] if cmd == "dot" => {
+            print!("{}", to_dot(include_str!("../input"), var)?);
+            return Ok(());
+        }
+        _ => bail!("usage: {} [equation <var> | dot <var>]", args[0]),
+    }
+
     let start = std::time::Instant::now();
     let soln_a = solve_a()?;
     eprintln!("Part A elapsed {:?}", start.elapsed());
@@ -16,180 +30,762 @@ pub fn main() -> Result<()> {
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum Operation {
     Add,
     Sub,
     Mult,
     Div,
-    Eq,
 }
 
-#[derive(Debug)]
-struct MathProb {
-    lhs: String,
-    rhs: String,
-    op: Operation,
-}
+impl Operation {
+    fn symbol(&self) -> char {
+        match self {
+            Operation::Add => '+',
+            Operation::Sub => '-',
+            Operation::Mult => '*',
+            Operation::Div => '/',
+        }
+    }
 
-impl MathProb {
-    fn solve(&self, known: &HashMap<String, i64>) -> Option<i64> {
-        match (known.get(&self.lhs), known.get(&self.rhs)) {
-            (Some(a), Some(b)) => Some(evaluate_op(*a, *b, &self.op)),
-            _ => None,
+    fn precedence(&self) -> u8 {
+        match self {
+            Operation::Add | Operation::Sub => 1,
+            Operation::Mult | Operation::Div => 2,
         }
     }
 }
 
-fn evaluate_op(lhs: i64, rhs: i64, op: &Operation) -> i64 {
-    match op {
-        Operation::Add => lhs + rhs,
-        Operation::Sub => lhs - rhs,
-        Operation::Mult => lhs * rhs,
-        Operation::Div => lhs / rhs,
-        _ => unreachable!(),
+fn evaluate_op(lhs: i64, rhs: i64, op: &Operation) -> Result<i64> {
+    let result = match op {
+        Operation::Add => lhs.checked_add(rhs),
+        Operation::Sub => lhs.checked_sub(rhs),
+        Operation::Mult => lhs.checked_mul(rhs),
+        Operation::Div if rhs == 0 => bail!("{} / 0 divides by zero", lhs),
+        Operation::Div => match lhs.checked_rem(rhs) {
+            Some(0) => lhs.checked_div(rhs),
+            Some(_) => bail!("{} / {} is not an exact division", lhs, rhs),
+            None => None,
+        },
+    };
+
+    result.ok_or_else(|| anyhow!("{} {} {} overflows", lhs, op.symbol(), rhs))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Node {
+    Value(i64),
+    Op(Operation, usize, usize),
+}
+
+// Monkey names are interned to indices into `nodes`. A name that is referenced but never defined
+// keeps a `None` node.
+#[derive(Debug, Default)]
+struct Monkeys {
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    nodes: Vec<Option<Node>>,
+}
+
+impl Monkeys {
+    fn intern(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+
+        self.names.push(name.to_string());
+        self.nodes.push(None);
+        self.ids.insert(name.to_string(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    fn id(&self, name: &str) -> Result<usize> {
+        self.ids
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("{} is never mentioned", name))
+    }
+
+    fn node(&self, id: usize) -> Result<Node> {
+        self.nodes[id].ok_or_else(|| anyhow!("{} is never defined", self.names[id]))
+    }
+
+    // Every node `root` depends on, ordered so that each comes after its operands. `leaf` is
+    // treated as an unknown and its own definition is not followed.
+    fn topo_order(&self, root: usize, leaf: Option<usize>) -> Result<Vec<usize>> {
+        const IN_PROGRESS: u8 = 1;
+        const DONE: u8 = 2;
+
+        let mut mark = vec![0; self.nodes.len()];
+        let mut order = vec![];
+        let mut stack = vec![(root, false)];
+
+        while let Some((id, expanded)) = stack.pop() {
+            if expanded {
+                mark[id] = DONE;
+                order.push(id);
+                continue;
+            }
+
+            if mark[id] == DONE {
+                continue;
+            }
+
+            mark[id] = IN_PROGRESS;
+            stack.push((id, true));
+
+            if Some(id) == leaf {
+                continue;
+            }
+
+            if let Node::Op(_, lhs, rhs) = self.node(id)? {
+                for x in [rhs, lhs] {
+                    match mark[x] {
+                        IN_PROGRESS => bail!(
+                            "{} depends on itself through {}",
+                            self.names[x],
+                            self.names[id]
+                        ),
+                        DONE => {}
+                        _ => stack.push((x, false)),
+                    }
+                }
+            }
+        }
+
+        Ok(order)
+    }
+
+    fn evaluate(&self, root: usize) -> Result<i64> {
+        let mut values = vec![0; self.nodes.len()];
+
+        for id in self.topo_order(root, None)? {
+            values[id] = match self.node(id)? {
+                Node::Value(x) => x,
+                Node::Op(op, lhs, rhs) => evaluate_op(values[lhs], values[rhs], &op)?,
+            };
+        }
+
+        Ok(values[root])
+    }
+
+    // Builds the expression for `root` with every subtree that doesn't involve `var` folded to a
+    // constant
+    fn reduce(&self, root: usize, var: Option<usize>) -> Result<Expr> {
+        let mut exprs: Vec<Option<Expr>> = vec![None; self.nodes.len()];
+
+        for id in self.topo_order(root, var)? {
+            if Some(id) == var {
+                exprs[id] = Some(Expr::Var(self.names[id].clone()));
+                continue;
+            }
+
+            let expr = match self.node(id)? {
+                Node::Value(x) => Expr::Const(Rational::int(x as i128)),
+                Node::Op(op, lhs, rhs) => {
+                    match (exprs[lhs].clone().unwrap(), exprs[rhs].clone().unwrap()) {
+                        (Expr::Const(a), Expr::Const(b)) => {
+                            let x = RatFn::constant(a).apply(&op, &RatFn::constant(b))?;
+                            Expr::Const(x.num.as_constant().unwrap())
+                        }
+                        (lhs, rhs) => Expr::Op(op, Box::new(lhs), Box::new(rhs)),
+                    }
+                }
+            };
+            exprs[id] = Some(expr);
+        }
+
+        Ok(exprs[root].take().unwrap())
     }
 }
 
-fn parse_input(input: &str) -> (HashMap<String, i64>, HashMap<String, MathProb>) {
-    input.lines().fold(
-        (HashMap::new(), HashMap::new()),
-        |(mut known, mut probs), line| {
-            let key = line[0..4].to_string();
+fn parse_input(input: &str) -> Result<Monkeys> {
+    let mut monkeys = Monkeys::default();
+
+    for (i, line) in input.lines().enumerate() {
+        let (name, job) = line
+            .split_once(": ")
+            .ok_or_else(|| anyhow!("line {}: expected 'name: job'", i + 1))?;
 
-            if line[6..7].chars().next().unwrap().is_ascii_digit() {
-                let val = line[6..].parse::<i64>().unwrap();
-                known.insert(key, val);
-            } else {
-                let lhs = line[6..10].to_string();
-                let rhs = line[13..].to_string();
-                let op = match &line[11..12] {
+        let node = match job.split_ascii_whitespace().collect::<Vec<_>>().as_slice() {
+            [x] => Node::Value(x.parse()?),
+            [lhs, op, rhs] => {
+                let op = match *op {
                     "+" => Operation::Add,
                     "-" => Operation::Sub,
                     "*" => Operation::Mult,
                     "/" => Operation::Div,
-                    _ => unreachable!(),
+                    _ => bail!("line {}: unknown operation {}", i + 1, op),
                 };
-                probs.insert(key, MathProb { lhs, rhs, op });
+                Node::Op(op, monkeys.intern(lhs), monkeys.intern(rhs))
             }
-            (known, probs)
-        },
-    )
+            _ => bail!("line {}: can't parse job '{}'", i + 1, job),
+        };
+
+        let id = monkeys.intern(name);
+        if monkeys.nodes[id].replace(node).is_some() {
+            bail!("line {}: {} is defined twice", i + 1, name);
+        }
+    }
+
+    Ok(monkeys)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rational {
+    num: i128,
+    den: i128,
+}
+
+// Every operation is checked, since the coefficients of the reduced equation can outgrow even
+// an i128
+impl Rational {
+    fn new(num: i128, den: i128) -> Result<Rational> {
+        let g = i128::try_from(gcd(num.unsigned_abs(), den.unsigned_abs()))
+            .ok()
+            .and_then(|g| g.checked_mul(den.signum()));
+
+        match g.and_then(|g| Some((num.checked_div(g)?, den.checked_div(g)?))) {
+            Some((num, den)) => Ok(Rational { num, den }),
+            None => bail!("{}/{} can't be represented", num, den),
+        }
+    }
+
+    fn int(x: i128) -> Rational {
+        Rational { num: x, den: 1 }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.num == 0
+    }
+
+    pub fn to_integer(self) -> Option<i128> {
+        (self.den == 1).then_some(self.num)
+    }
+
+    fn checked(
+        self,
+        op: char,
+        other: Rational,
+        num: Option<i128>,
+        den: Option<i128>,
+    ) -> Result<Rational> {
+        match (num, den) {
+            (Some(num), Some(den)) => Rational::new(num, den),
+            _ => bail!("{} {} {} overflows", self, op, other),
+        }
+    }
+
+    fn add(self, other: Rational) -> Result<Rational> {
+        let num = self
+            .num
+            .checked_mul(other.den)
+            .zip(other.num.checked_mul(self.den))
+            .and_then(|(a, b)| a.checked_add(b));
+        self.checked('+', other, num, self.den.checked_mul(other.den))
+    }
+
+    fn sub(self, other: Rational) -> Result<Rational> {
+        let num = self
+            .num
+            .checked_mul(other.den)
+            .zip(other.num.checked_mul(self.den))
+            .and_then(|(a, b)| a.checked_sub(b));
+        self.checked('-', other, num, self.den.checked_mul(other.den))
+    }
+
+    fn mul(self, other: Rational) -> Result<Rational> {
+        let num = self.num.checked_mul(other.num);
+        self.checked('*', other, num, self.den.checked_mul(other.den))
+    }
+
+    fn div(self, other: Rational) -> Result<Rational> {
+        if other.is_zero() {
+            bail!("{} / 0 divides by zero", self);
+        }
+        let num = self.num.checked_mul(other.den);
+        self.checked('/', other, num, self.den.checked_mul(other.num))
+    }
 }
 
-fn solve_root(known: &mut HashMap<String, i64>, probs: &mut HashMap<String, MathProb>) -> i64 {
-    let to_solve = build_branch("root".to_string(), known, probs);
-    traverse_solve(&to_solve, known, probs);
+impl std::fmt::Display for Rational {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.den {
+            1 => write!(f, "{}", self.num),
+            _ => write!(f, "{}/{}", self.num, self.den),
+        }
+    }
+}
 
-    *known.get("root").unwrap()
+fn gcd(a: u128, b: u128) -> u128 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
 }
 
-fn traverse_solve(
-    to_solve: &[String],
-    known: &mut HashMap<String, i64>,
-    probs: &mut HashMap<String, MathProb>,
-) {
-    for x in to_solve.iter().rev() {
-        if known.contains_key(x) {
-            continue;
-        } else if probs.contains_key(x) {
-            let mp = probs.get(x).unwrap();
-            let soln = mp.solve(known).unwrap();
-            known.insert(x.to_owned(), soln);
-        } else {
-            unreachable!();
+// Polynomial in the unknown with the constant term first
+#[derive(Debug, Clone, PartialEq)]
+struct Poly(Vec<Rational>);
+
+impl Poly {
+    fn constant(x: Rational) -> Poly {
+        Poly(vec![x]).trim()
+    }
+
+    fn var() -> Poly {
+        Poly(vec![Rational::int(0), Rational::int(1)])
+    }
+
+    fn trim(mut self) -> Poly {
+        while self.0.last().is_some_and(|c| c.is_zero()) {
+            self.0.pop();
+        }
+        self
+    }
+
+    fn degree(&self) -> Option<usize> {
+        self.0.len().checked_sub(1)
+    }
+
+    fn as_constant(&self) -> Option<Rational> {
+        match self.0.len() {
+            0 => Some(Rational::int(0)),
+            1 => Some(self.0[0]),
+            _ => None,
+        }
+    }
+
+    fn eval(&self, x: Rational) -> Result<Rational> {
+        self.0
+            .iter()
+            .rev()
+            .try_fold(Rational::int(0), |acc, c| acc.mul(x)?.add(*c))
+    }
+
+    fn add(&self, other: &Poly) -> Result<Poly> {
+        let n = std::cmp::max(self.0.len(), other.0.len());
+        let zero = Rational::int(0);
+        let coeffs = (0..n)
+            .map(|i| {
+                let a = self.0.get(i).unwrap_or(&zero);
+                let b = other.0.get(i).unwrap_or(&zero);
+                a.add(*b)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Poly(coeffs).trim())
+    }
+
+    fn scale(&self, x: Rational) -> Result<Poly> {
+        let coeffs = self
+            .0
+            .iter()
+            .map(|c| c.mul(x))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Poly(coeffs).trim())
+    }
+
+    fn mul(&self, other: &Poly) -> Result<Poly> {
+        let mut out = vec![Rational::int(0); self.0.len() + other.0.len()];
+        for (i, a) in self.0.iter().enumerate() {
+            for (j, b) in other.0.iter().enumerate() {
+                out[i + j] = out[i + j].add(a.mul(*b)?)?;
+            }
         }
+        Ok(Poly(out).trim())
     }
 }
 
-fn build_branch(
-    start: String,
-    known: &HashMap<String, i64>,
-    probs: &HashMap<String, MathProb>,
-) -> Vec<String> {
-    let mut queue = VecDeque::from([start.clone()]);
-    let mut to_solve = Vec::new();
-    to_solve.push(start);
+// num / den, kept with den == 1 whenever the denominator doesn't involve the unknown
+#[derive(Debug, Clone)]
+struct RatFn {
+    num: Poly,
+    den: Poly,
+}
+
+impl RatFn {
+    fn constant(x: Rational) -> RatFn {
+        RatFn {
+            num: Poly::constant(x),
+            den: Poly::constant(Rational::int(1)),
+        }
+    }
+
+    fn new(num: Poly, den: Poly) -> Result<RatFn> {
+        Ok(match den.as_constant() {
+            Some(c) if !c.is_zero() => RatFn {
+                num: num.scale(Rational::int(1).div(c)?)?,
+                den: Poly::constant(Rational::int(1)),
+            },
+            _ => RatFn { num, den },
+        })
+    }
+
+    fn apply(&self, op: &Operation, other: &RatFn) -> Result<RatFn> {
+        let (a, b, c, d) = (&self.num, &self.den, &other.num, &other.den);
+
+        match op {
+            Operation::Add => RatFn::new(a.mul(d)?.add(&c.mul(b)?)?, b.mul(d)?),
+            Operation::Sub => RatFn::new(
+                a.mul(d)?.add(&c.mul(b)?.scale(Rational::int(-1))?)?,
+                b.mul(d)?,
+            ),
+            Operation::Mult => RatFn::new(a.mul(c)?, b.mul(d)?),
+            Operation::Div => {
+                if c.degree().is_none() {
+                    bail!("division by zero");
+                }
+                RatFn::new(a.mul(d)?, b.mul(c)?)
+            }
+        }
+    }
+}
 
-    while !queue.is_empty() {
-        let current = queue.pop_front().unwrap();
+#[derive(Debug, Clone)]
+enum Expr {
+    Const(Rational),
+    Var(String),
+    Op(Operation, Box<Expr>, Box<Expr>),
+}
 
-        if known.contains_key(&current) {
-            continue;
+impl Expr {
+    fn occurrences(&self) -> usize {
+        match self {
+            Expr::Const(_) => 0,
+            Expr::Var(_) => 1,
+            Expr::Op(_, lhs, rhs) => lhs.occurrences() + rhs.occurrences(),
         }
+    }
 
-        let mp = probs.get(&current).unwrap();
+    fn to_ratfn(&self) -> Result<RatFn> {
+        match self {
+            Expr::Const(x) => Ok(RatFn::constant(*x)),
+            Expr::Var(_) => RatFn::new(Poly::var(), Poly::constant(Rational::int(1))),
+            Expr::Op(op, lhs, rhs) => lhs.to_ratfn()?.apply(op, &rhs.to_ratfn()?),
+        }
+    }
+}
 
-        queue.push_back(mp.lhs.clone());
-        queue.push_back(mp.rhs.clone());
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expr::Const(x) if x.num < 0 || x.den != 1 => write!(f, "({})", x),
+            Expr::Const(x) => write!(f, "{}", x),
+            Expr::Var(name) => write!(f, "{}", name),
+            Expr::Op(op, lhs, rhs) => {
+                let prec = |e: &Expr| match e {
+                    Expr::Op(op, _, _) => op.precedence(),
+                    _ => u8::MAX,
+                };
 
-        to_solve.push(mp.lhs.clone());
-        to_solve.push(mp.rhs.clone());
+                // Only the right operand of - and / needs brackets at equal precedence
+                let lhs_brackets = prec(lhs) < op.precedence();
+                let rhs_brackets = prec(rhs) < op.precedence()
+                    || (prec(rhs) == op.precedence()
+                        && matches!(op, Operation::Sub | Operation::Div));
+
+                match lhs_brackets {
+                    true => write!(f, "({})", lhs)?,
+                    false => write!(f, "{}", lhs)?,
+                }
+                write!(f, " {} ", op.symbol())?;
+                match rhs_brackets {
+                    true => write!(f, "({})", rhs),
+                    false => write!(f, "{}", rhs),
+                }
+            }
+        }
     }
+}
 
-    to_solve
+#[derive(Debug, Clone, PartialEq)]
+pub enum Solution {
+    Unique(Rational),
+    // Every one of these works
+    Multiple(Vec<Rational>),
+    NoSolution,
+    AnyValue,
+    // A polynomial of this degree is left to solve, so whether there's a solution, or only one,
+    // isn't known
+    Unsolved { degree: usize },
 }
 
-fn reduce_problems(known: &mut HashMap<String, i64>, probs: &mut HashMap<String, MathProb>) {
-    loop {
-        let n_unsolved = probs.len();
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolveReport {
+    pub occurrences: usize,
+    pub solution: Solution,
+}
+
+// Treats `root` as an equality and solves it for `var`
+pub fn solve_for(input: &str, var: &str) -> Result<SolveReport> {
+    let monkeys = parse_input(input)?;
+
+    let (lhs, rhs) = match monkeys.node(monkeys.id("root")?)? {
+        Node::Op(_, lhs, rhs) => (lhs, rhs),
+        Node::Value(_) => bail!("root is not an operation"),
+    };
+
+    let var = monkeys.ids.get(var).copied();
+    let lhs = monkeys.reduce(lhs, var)?;
+    let rhs = monkeys.reduce(rhs, var)?;
+
+    let occurrences = lhs.occurrences() + rhs.occurrences();
+    let (lhs, rhs) = (lhs.to_ratfn()?, rhs.to_ratfn()?);
+
+    // lhs.num / lhs.den == rhs.num / rhs.den wherever neither denominator is zero
+    let eq = lhs
+        .num
+        .mul(&rhs.den)?
+        .add(&rhs.num.mul(&lhs.den)?.scale(Rational::int(-1))?)?;
+    let defined = |x: Rational| -> Result<bool> {
+        Ok(!lhs.den.eval(x)?.is_zero() && !rhs.den.eval(x)?.is_zero())
+    };
+
+    // Factors of the unknown itself give a root at 0, and taking them out may leave something
+    // linear. Roots where a denominator vanishes are dropped, which is what cancelling a factor
+    // shared by a numerator and its denominator amounts to.
+    let zeros = eq.0.iter().take_while(|c| c.is_zero()).count();
+    let rest = Poly(eq.0[zeros..].to_vec());
+
+    let solution = match rest.degree() {
+        None => Solution::AnyValue,
+        Some(degree) if degree > 1 => Solution::Unsolved { degree },
+        degree => {
+            let mut roots = vec![];
+            if zeros > 0 {
+                roots.push(Rational::int(0));
+            }
+            if degree == Some(1) {
+                roots.push(Rational::int(0).sub(rest.0[0])?.div(rest.0[1])?);
+            }
+
+            let mut valid = vec![];
+            for x in roots {
+                if defined(x)? {
+                    valid.push(x);
+                }
+            }
 
-        probs.retain(|name, mp| {
-            if let Some(val) = mp.solve(known) {
-                known.insert(name.to_owned(), val);
-                false
-            } else {
-                true
+            match valid.len() {
+                0 => Solution::NoSolution,
+                1 => Solution::Unique(valid[0]),
+                _ => Solution::Multiple(valid),
             }
-        });
+        }
+    };
+
+    Ok(SolveReport {
+        occurrences,
+        solution,
+    })
+}
+
+// The root equality as an infix formula, with everything that doesn't depend on `var` folded
+pub fn equation(input: &str, var: &str) -> Result<String> {
+    let monkeys = parse_input(input)?;
+
+    let (lhs, rhs) = match monkeys.node(monkeys.id("root")?)? {
+        Node::Op(_, lhs, rhs) => (lhs, rhs),
+        Node::Value(_) => bail!("root is not an operation"),
+    };
+
+    let var = monkeys.ids.get(var).copied();
+
+    Ok(format!(
+        "{} = {}",
+        monkeys.reduce(lhs, var)?,
+        monkeys.reduce(rhs, var)?
+    ))
+}
+
+// The dependency graph below root in Graphviz DOT, with the path from root to `var` highlighted
+pub fn to_dot(input: &str, var: &str) -> Result<String> {
+    let monkeys = parse_input(input)?;
+    let root = monkeys.id("root")?;
+    let var = monkeys.ids.get(var).copied();
+
+    let order = monkeys.topo_order(root, var)?;
+    let mut on_path = vec![false; monkeys.nodes.len()];
+    for &id in order.iter() {
+        on_path[id] = match monkeys.node(id) {
+            _ if Some(id) == var => true,
+            Ok(Node::Op(_, lhs, rhs)) => on_path[lhs] || on_path[rhs],
+            _ => false,
+        };
+    }
+
+    let highlight = |x: bool| if x { ", color=red, penwidth=2" } else { "" };
 
-        if probs.len() == n_unsolved {
-            break;
+    let mut out = vec!["digraph monkeys {".to_string()];
+    for &id in order.iter().rev() {
+        let name = &monkeys.names[id];
+        let label = match monkeys.node(id) {
+            _ if Some(id) == var => name.to_string(),
+            Ok(Node::Value(x)) => format!("{}: {}", name, x),
+            Ok(Node::Op(op, _, _)) => format!("{}: {}", name, op.symbol()),
+            Err(e) => return Err(e),
+        };
+        out.push(format!(
+            "    \"{}\" [label=\"{}\"{}];",
+            name,
+            label,
+            highlight(on_path[id])
+        ));
+
+        if let (false, Ok(Node::Op(_, lhs, rhs))) = (Some(id) == var, monkeys.node(id)) {
+            for x in [lhs, rhs] {
+                out.push(format!(
+                    "    \"{}\" -> \"{}\" [{}];",
+                    name,
+                    monkeys.names[x],
+                    highlight(on_path[id] && on_path[x]).trim_start_matches(", ")
+                ));
+            }
         }
     }
+    out.push("}".to_string());
+
+    Ok(out.join("\n") + "\n")
 }
 
 pub fn solve_a() -> Result<i64> {
-    let (mut known, mut problems) = parse_input(include_str!("../input"));
+    let monkeys = parse_input(include_str!("../input"))?;
 
-    let x = solve_root(&mut known, &mut problems);
-
-    Ok(x)
+    monkeys.evaluate(monkeys.id("root")?)
 }
 
 pub fn solve_b() -> Result<i64> {
-    let (mut known, mut problems) = parse_input(include_str!("../input"));
-
-    known.remove(&String::from("humn"));
-    if let Some(p) = problems.get_mut(&String::from("root")) {
-        p.op = Operation::Eq;
-    } else {
-        panic!();
-    }
-
-    reduce_problems(&mut known, &mut problems);
-
-    let mut search = String::from("root");
-    let mut soln = 0;
-
-    while search != *"humn" {
-        let mp = problems.get(&search).unwrap();
-        let clhs = mp.lhs.to_string();
-        let crhs = mp.rhs.to_string();
-        (search, soln) = match (&mp.op, known.get(&mp.lhs), known.get(&mp.rhs)) {
-            (Operation::Add, Some(x), None) => (crhs, soln - x),
-            (Operation::Add, None, Some(x)) => (clhs, soln - x),
-            (Operation::Sub, Some(x), None) => (crhs, x - soln),
-            (Operation::Sub, None, Some(x)) => (clhs, soln + x),
-            (Operation::Mult, Some(x), None) => (crhs, soln / x),
-            (Operation::Mult, None, Some(x)) => (clhs, soln / x),
-            (Operation::Div, Some(x), None) => (crhs, x / soln),
-            (Operation::Div, None, Some(x)) => (clhs, soln * x),
-            (Operation::Eq, Some(x), None) => (crhs, *x),
-            (Operation::Eq, None, Some(x)) => (clhs, *x),
-            _ => unreachable!(),
-        };
+    solve_integer(include_str!("../input"), "humn")
+}
+
+// The value of `var` that makes root's equality hold, which has to be unique and fit an i64
+fn solve_integer(input: &str, var: &str) -> Result<i64> {
+    match solve_for(input, var)?.solution {
+        Solution::Unique(x) => match x.to_integer() {
+            Some(n) => i64::try_from(n).map_err(|_| anyhow!("{} = {} overflows", var, n)),
+            None => bail!("{} must be {}, which is not an integer", var, x),
+        },
+        s => bail!("{} has no unique solution: {:?}", var, s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let monkeys = parse_input(include_str!("../example1")).unwrap();
+        assert_eq!(monkeys.evaluate(monkeys.id("root").unwrap()).unwrap(), 152);
+
+        let monkeys = parse_input("root: aaaa + bbbb\naaaa: bbbb * cccc\nbbbb: 3\n").unwrap();
+        let err = monkeys.evaluate(monkeys.id("root").unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "cccc is never defined");
+
+        let monkeys =
+            parse_input("root: aaaa + bbbb\naaaa: bbbb * cccc\nbbbb: 3\ncccc: root - bbbb\n")
+                .unwrap();
+        assert!(monkeys.evaluate(monkeys.id("root").unwrap()).is_err());
+
+        let monkeys = parse_input("root: aaaa * aaaa\naaaa: 4294967296\n").unwrap();
+        let err = monkeys.evaluate(monkeys.id("root").unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "4294967296 * 4294967296 overflows");
+
+        let monkeys = parse_input("root: aaaa / bbbb\naaaa: 7\nbbbb: 2\n").unwrap();
+        let err = monkeys.evaluate(monkeys.id("root").unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "7 / 2 is not an exact division");
+
+        assert!(parse_input("root: 3\nroot: 4\n").is_err());
+        assert!(parse_input("root: aaaa % bbbb\n").is_err());
     }
 
-    Ok(soln)
+    #[test]
+    fn test_equation() {
+        let input = include_str!("../example1");
+
+        assert_eq!(
+            equation(input, "humn").unwrap(),
+            "(4 + 2 * (humn - 3)) / 4 = 150"
+        );
+        assert_eq!(equation(input, "zzzz").unwrap(), "2 = 150");
+
+        let dot = to_dot(input, "humn").unwrap();
+        assert!(dot.starts_with("digraph monkeys {\n    \"root\" [label=\"root: +\", color=red"));
+        assert!(dot.contains("    \"root\" -> \"pppw\" [color=red, penwidth=2];\n"));
+        assert!(dot.contains("    \"root\" -> \"sjmn\" [];\n"));
+        assert!(dot.contains("    \"humn\" [label=\"humn\", color=red, penwidth=2];\n"));
+    }
+
+    #[test]
+    fn test_solve_for() {
+        let input = include_str!("../example1");
+
+        let report = solve_for(input, "humn").unwrap();
+        assert_eq!(report.occurrences, 1);
+        assert_eq!(report.solution, Solution::Unique(Rational::int(301)));
+        assert_eq!(solve_integer(input, "humn").unwrap(), 301);
+
+        let report = solve_for(input, "zzzz").unwrap();
+        assert_eq!(report.occurrences, 0);
+        assert_eq!(report.solution, Solution::NoSolution);
+
+        let input = "root: humn + nope\nhumn: 5\n";
+        assert!(solve_for(input, "humn").is_err());
+
+        // (humn - 3) * 2 + 4 == humn + (1 / 3)
+        let input = "root: lhs1 + rhs1\nlhs1: lhs2 + four\nlhs2: lhs3 * twoo\nlhs3: humn - thre\nrhs1: humn + rhs2\nrhs2: onee / thre\nfour: 4\ntwoo: 2\nthre: 3\nonee: 1\nhumn: 5\n";
+        let report = solve_for(input, "humn").unwrap();
+        assert_eq!(report.occurrences, 2);
+        assert_eq!(
+            report.solution,
+            Solution::Unique(Rational::new(7, 3).unwrap())
+        );
+
+        // humn * humn == 4 has two roots, but quadratics aren't solved
+        let input = "root: sqre + four\nsqre: humn * humn\nfour: 4\nhumn: 5\n";
+        let report = solve_for(input, "humn").unwrap();
+        assert_eq!(report.solution, Solution::Unsolved { degree: 2 });
+        let err = solve_integer(input, "humn").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "humn has no unique solution: Unsolved { degree: 2 }"
+        );
+
+        // humn * humn / humn == 3, where humn == 0 would divide by zero
+        let input = "root: quot + thre\nquot: sqre / humn\nsqre: humn * humn\nthre: 3\nhumn: 5\n";
+        let report = solve_for(input, "humn").unwrap();
+        assert_eq!(report.solution, Solution::Unique(Rational::int(3)));
+
+        // humn * humn == humn * 3
+        let input = "root: sqre + tims\nsqre: humn * humn\ntims: humn * thre\nthre: 3\nhumn: 5\n";
+        let report = solve_for(input, "humn").unwrap();
+        assert_eq!(
+            report.solution,
+            Solution::Multiple(vec![Rational::int(0), Rational::int(3)])
+        );
+
+        // humn - humn == 0
+        let input = "root: diff + zero\ndiff: humn - humn\nzero: 0\nhumn: 5\n";
+        let report = solve_for(input, "humn").unwrap();
+        assert_eq!(report.solution, Solution::AnyValue);
+
+        // humn * 10^54 overflows an i128 before the equation can be solved
+        let input = "root: aaaa + zero\naaaa: bbbb * bigg\nbbbb: cccc * bigg\ncccc: humn * bigg\nbigg: 1000000000000000000\nzero: 0\nhumn: 5\n";
+        let err = solve_for(input, "humn").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1000000000000000000000000000000000000 * 1000000000000000000 overflows"
+        );
+
+        // humn == 2^63 solves fine as a Rational, but doesn't fit the i64 answer
+        let input = "root: humn + aaaa
+aaaa: bbbb * twoo
+bbbb: 4611686018427387904
+twoo: 2
+humn: 5
+";
+        assert!(solve_for(input, "humn").is_ok());
+        let err = solve_integer(input, "humn").unwrap_err();
+        assert_eq!(err.to_string(), "humn = 9223372036854775808 overflows");
+    }
 }
@@ -1,6 +1,5 @@
-use std::collections::HashMap;
-
 use anyhow::Result;
+use cycle::Cycle;
 use pathfinding::prelude::dijkstra;
 
 pub fn main() -> Result<()> {
@@ -106,30 +105,6 @@ fn next_state(s: &State) -> State {
     ns
 }
 
-fn build_states(init_state: &State) -> (Vec<State>, usize) {
-    let mut cache: HashMap<State, usize> = HashMap::new();
-    let mut states = vec![init_state.clone()];
-    let mut state = init_state.to_vec();
-
-    cache.insert(init_state.to_vec(), 0);
-
-    let cycle_start_idx = loop {
-        let ns = next_state(&state);
-        let clen = cache.len();
-
-        let idx = *cache.entry(ns.clone()).or_insert(clen);
-        if idx != clen {
-            break idx;
-        }
-
-        states.push(ns.clone());
-
-        state = ns.clone();
-    };
-
-    (states, cycle_start_idx)
-}
-
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 struct Pos {
     x: usize,
@@ -138,17 +113,11 @@ struct Pos {
 }
 
 impl Pos {
-    fn successors(&self, states: &[State], cycle_start_idx: usize) -> Vec<(Pos, usize)> {
+    fn successors(&self, states: &[State], cycle: &Cycle) -> Vec<(Pos, usize)> {
         let x = self.x;
         let y = self.y;
 
-        let state_idx = if self.t < cycle_start_idx {
-            self.t + 1
-        } else {
-            cycle_start_idx + (self.t + 1 - cycle_start_idx) % (states.len() - cycle_start_idx)
-        };
-
-        let s = &states[state_idx];
+        let s = &states[cycle.index(self.t + 1)];
         let tnext = self.t + 1;
 
         let nrows = s.len();
@@ -201,7 +170,7 @@ fn solve_path_len(
     start: (usize, usize),
     end: (usize, usize),
     init_time: usize,
-    cycle_start_idx: usize,
+    cycle: &Cycle,
     states: &[State],
 ) -> usize {
     let path = dijkstra(
@@ -210,7 +179,7 @@ fn solve_path_len(
             y: start.1,
             t: init_time,
         },
-        |p| p.successors(states, cycle_start_idx),
+        |p| p.successors(states, cycle),
         |p| p.x == end.0 && p.y == end.1,
     );
 
@@ -219,34 +188,34 @@ fn solve_path_len(
 
 pub fn solve_a() -> Result<usize> {
     let state = parse_input(include_bytes!("../input"));
-    let (states, cycle_start_idx) = build_states(&state);
+    let (states, cycle) = cycle::detect_states(state.clone(), next_state);
 
     let nrows = state.len();
     let ncols = state[0].len();
 
     let goal = (nrows - 1, ncols - 2);
 
-    let path_len = solve_path_len((0, 1), goal, 0, cycle_start_idx, &states);
+    let path_len = solve_path_len((0, 1), goal, 0, &cycle, &states);
 
     Ok(path_len)
 }
 
 pub fn solve_b() -> Result<usize> {
     let state = parse_input(include_bytes!("../input"));
-    let (states, cycle_start_idx) = build_states(&state);
+    let (states, cycle) = cycle::detect_states(state.clone(), next_state);
 
     let nrows = state.len();
     let ncols = state[0].len();
 
     let goal = (nrows - 1, ncols - 2);
 
-    let path_len1 = solve_path_len((0, 1), goal, 0, cycle_start_idx, &states);
-    let path_len2 = solve_path_len(goal, (0, 1), path_len1, cycle_start_idx, &states);
+    let path_len1 = solve_path_len((0, 1), goal, 0, &cycle, &states);
+    let path_len2 = solve_path_len(goal, (0, 1), path_len1, &cycle, &states);
     let path_len3 = solve_path_len(
         (0, 1),
         goal,
         path_len1 + path_len2,
-        cycle_start_idx,
+        &cycle,
         &states,
     );
 
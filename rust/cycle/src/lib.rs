@@ -0,0 +1,281 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cycle {
+    pub start: usize,
+    pub length: usize,
+}
+
+impl Cycle {
+    // Maps step `n` onto the equivalent step within the first pass through the cycle
+    pub fn index(&self, n: usize) -> usize {
+        if n < self.start {
+            n
+        } else {
+            self.start + (n - self.start) % self.length
+        }
+    }
+
+    // Extrapolates a quantity that grows by a fixed amount every period. `values[i]` is the
+    // quantity after step i and must cover at least `start + length` steps.
+    pub fn extrapolate(&self, n: usize, values: &[usize]) -> usize {
+        if n <= self.start + self.length {
+            return values[n];
+        }
+
+        let repeats = (n - self.start) / self.length;
+        let offset = (n - self.start) % self.length;
+        let delta = values[self.start + self.length] - values[self.start];
+
+        values[self.start + offset] + repeats * delta
+    }
+}
+
+pub fn floyd<S: PartialEq>(x0: S, f: impl Fn(&S) -> S) -> Cycle {
+    let mut tortoise = f(&x0);
+    let mut hare = f(&tortoise);
+    while tortoise != hare {
+        tortoise = f(&tortoise);
+        hare = f(&f(&hare));
+    }
+
+    let mut start = 0;
+    tortoise = x0;
+    while tortoise != hare {
+        tortoise = f(&tortoise);
+        hare = f(&hare);
+        start += 1;
+    }
+
+    let mut length = 1;
+    hare = f(&tortoise);
+    while tortoise != hare {
+        hare = f(&hare);
+        length += 1;
+    }
+
+    Cycle { start, length }
+}
+
+pub fn brent<S: Clone + PartialEq>(x0: S, f: impl Fn(&S) -> S) -> Cycle {
+    let mut power = 1;
+    let mut length = 1;
+    let mut tortoise = x0.clone();
+    let mut hare = f(&x0);
+    while tortoise != hare {
+        if power == length {
+            tortoise = hare.clone();
+            power *= 2;
+            length = 0;
+        }
+        hare = f(&hare);
+        length += 1;
+    }
+
+    let mut tortoise = x0.clone();
+    let mut hare = x0;
+    for _ in 0..length {
+        hare = f(&hare);
+    }
+
+    let mut start = 0;
+    while tortoise != hare {
+        tortoise = f(&tortoise);
+        hare = f(&hare);
+        start += 1;
+    }
+
+    Cycle { start, length }
+}
+
+// Finds the first repeated key in a sequence, where key i describes the state after step i.
+// Returns None if the sequence ends before anything repeats.
+pub fn detect<K: Hash + Eq>(keys: impl IntoIterator<Item = K>) -> Option<Cycle> {
+    let mut seen: HashMap<K, usize> = HashMap::new();
+
+    for (i, key) in keys.into_iter().enumerate() {
+        match seen.entry(key) {
+            Entry::Occupied(e) => {
+                let start = *e.get();
+                return Some(Cycle {
+                    start,
+                    length: i - start,
+                });
+            }
+            Entry::Vacant(e) => {
+                e.insert(i);
+            }
+        }
+    }
+
+    None
+}
+
+// Like `detect`, but a repeated key is only accepted once the following `periods` full periods
+// also match. This filters out coincidental repeats of keys that only approximate the underlying
+// state, but it can't prove a cycle: if the keys hide a difference that persists across periods,
+// every period will still match.
+pub fn detect_verified<K: Hash + Eq + Clone>(
+    keys: impl IntoIterator<Item = K>,
+    periods: usize,
+) -> Option<Cycle> {
+    let mut seen: HashMap<K, usize> = HashMap::new();
+    let mut history = vec![];
+    let mut candidate: Option<(Cycle, usize)> = None;
+
+    for (i, key) in keys.into_iter().enumerate() {
+        if let Some((c, found)) = candidate {
+            if key != history[i - c.length] {
+                candidate = None;
+            } else if i == found + periods * c.length {
+                return Some(c);
+            }
+        }
+
+        if candidate.is_none() {
+            if let Some(&prev) = seen.get(&key) {
+                let c = Cycle {
+                    start: prev,
+                    length: i - prev,
+                };
+                if periods == 0 {
+                    return Some(c);
+                }
+                candidate = Some((c, i));
+            }
+        }
+
+        seen.insert(key.clone(), i);
+        history.push(key);
+    }
+
+    None
+}
+
+// Steps `f` from `x0` until a state repeats, returning every state up to the end of the first
+// pass through the cycle.
+pub fn detect_states<S: Clone + Hash + Eq>(x0: S, mut f: impl FnMut(&S) -> S) -> (Vec<S>, Cycle) {
+    let mut seen: HashMap<S, usize> = HashMap::new();
+    let mut states = vec![];
+    let mut state = x0;
+
+    loop {
+        match seen.entry(state.clone()) {
+            Entry::Occupied(e) => {
+                let start = *e.get();
+                let length = states.len() - start;
+                return (states, Cycle { start, length });
+            }
+            Entry::Vacant(e) => {
+                e.insert(states.len());
+            }
+        }
+
+        let next = f(&state);
+        states.push(state);
+        state = next;
+    }
+}
+
+pub fn fast_forward<S: Clone + Hash + Eq>(x0: S, mut f: impl FnMut(&S) -> S, n: usize) -> S {
+    let mut seen: HashMap<S, usize> = HashMap::new();
+    let mut states = vec![];
+    let mut state = x0;
+
+    for i in 0..n {
+        match seen.entry(state.clone()) {
+            Entry::Occupied(e) => {
+                let start = *e.get();
+                let cycle = Cycle {
+                    start,
+                    length: i - start,
+                };
+                return states.swap_remove(cycle.index(n));
+            }
+            Entry::Vacant(e) => {
+                e.insert(i);
+            }
+        }
+
+        let next = f(&state);
+        states.push(state);
+        state = next;
+    }
+
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(x: &u32) -> u32 {
+        (x * x + 1) % 255
+    }
+
+    #[test]
+    fn test_detectors_agree() {
+        for x0 in 0..255 {
+            let c = floyd(x0, step);
+            assert_eq!(brent(x0, step), c);
+            assert_eq!(detect_states(x0, step).1, c);
+
+            let keys = std::iter::successors(Some(x0), |x| Some(step(x)));
+            assert_eq!(detect(keys), Some(c));
+        }
+    }
+
+    #[test]
+    fn test_detect_verified() {
+        // 1 2 3 repeats once before the real cycle of 1 2 3 4 begins
+        let keys = [0, 1, 2, 3, 1, 2, 3, 4, 1, 2, 3, 4, 1, 2, 3, 4];
+
+        assert_eq!(
+            detect(keys),
+            Some(Cycle {
+                start: 1,
+                length: 3
+            })
+        );
+        assert_eq!(
+            detect_verified(keys, 1),
+            Some(Cycle {
+                start: 4,
+                length: 4
+            })
+        );
+        assert_eq!(detect_verified(keys, 3), None);
+    }
+
+    #[test]
+    fn test_fast_forward() {
+        for n in 0..300 {
+            let mut x = 3;
+            for _ in 0..n {
+                x = step(&x);
+            }
+            assert_eq!(fast_forward(3, step, n), x);
+        }
+    }
+
+    #[test]
+    fn test_extrapolate() {
+        // 2 steps of lead-in, then a period of 3 that grows by 10
+        let values = [0, 1, 3, 5, 9, 13];
+        let c = Cycle {
+            start: 2,
+            length: 3,
+        };
+
+        let expected = |n: usize| match n {
+            0 | 1 => values[n],
+            _ => values[2 + (n - 2) % 3] + 10 * ((n - 2) / 3),
+        };
+
+        for n in 0..50 {
+            assert_eq!(c.extrapolate(n, &values), expected(n));
+        }
+    }
+}
@@ -0,0 +1,22 @@
+// Small linear congruential generator, so tests can make reproducible pseudo-random inputs
+// without pulling in a crate for it
+#[derive(Debug, Clone)]
+pub struct Lcg {
+    state: u64,
+}
+
+impl Lcg {
+    pub fn new(seed: u64) -> Lcg {
+        Lcg { state: seed }
+    }
+
+    // Roughly uniform in 0..n. Only the high bits are used, since the low bits of an LCG have
+    // short periods.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.state = self
+            .state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.state >> 33) % n
+    }
+}